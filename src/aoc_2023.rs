@@ -15,3 +15,28 @@ mod day_6;
 mod day_7;
 mod day_8;
 mod day_9;
+
+/// A xorshift generator for the randomized tests, seeded so that failures are
+/// reproducible.
+#[cfg(test)]
+struct Rng(u64);
+
+#[cfg(test)]
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        assert_ne!(seed, 0, "xorshift needs a non-zero seed");
+        Rng(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
@@ -1,66 +1,268 @@
+use std::collections::{HashMap, VecDeque};
+
 fn part_1(input: &str) -> usize {
-    let mut sum = 0;
-    for line in input.lines() {
-        sum += parse_line(&line);
-    }
-    return sum;
+    let matcher = DigitMatcher::new(&Vocabulary::new(&[]).unwrap());
+    calibrate(input, &matcher, BadLines::Abort).unwrap().sum
 }
 
-fn parse_line(line: &str) -> usize {
-    let nums = line
-        .chars()
-        .map(|c| c.to_digit(10))
-        .filter(Option::is_some)
-        .map(Option::unwrap)
-        .map(|n| n as usize)
-        .collect::<Vec<usize>>();
-    return (nums.first().unwrap() * 10) + nums.last().unwrap();
+fn part_2(input: &str) -> usize {
+    let matcher = DigitMatcher::new(&Vocabulary::english());
+    calibrate(input, &matcher, BadLines::Abort).unwrap().sum
 }
 
-fn part_2(input: &str) -> usize {
-    let mut sum = 0;
-    for line in input.lines() {
-        let first = get_first(&line);
-        let last = get_last(&line);
-        let number = (first * 10) + last;
-        sum += number;
+/// A calibration line without any digit in it. `line` counts from 1.
+#[derive(Debug, Eq, PartialEq)]
+struct NoDigit {
+    line: usize,
+    text: String,
+}
+
+impl std::fmt::Display for NoDigit {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "no digit found on line {}: {:?}", self.line, self.text)
     }
-    return sum;
 }
 
-fn get_first(line: &str) -> usize {
-    for i in 0..line.len() {
-        match start_digit(&line[i..]) {
-            Some(x) => return x,
-            None => continue,
+impl std::error::Error for NoDigit {}
+
+/// What to do with a line that has no digit.
+#[derive(Clone, Copy)]
+enum BadLines {
+    /// Stop at the first one.
+    Abort,
+    /// Leave it out of the sum.
+    Skip,
+    /// Leave it out of the sum, but list it in `Calibration::bad_lines`.
+    Report,
+}
+
+struct Calibration {
+    sum: usize,
+    bad_lines: Vec<NoDigit>,
+}
+
+/// The first digit and the last digit of each line as a two-digit number. On ties
+/// the longer word wins, so `ix` is read as 9 rather than 1 if both are words.
+fn calibration_values<'a>(
+    input: &'a str,
+    matcher: &'a DigitMatcher,
+) -> impl Iterator<Item = Result<usize, NoDigit>> + 'a {
+    input.lines().enumerate().map(|(idx, line)| {
+        let digits = matcher.find(line);
+        let no_digit = || NoDigit {
+            line: idx + 1,
+            text: String::from(line),
+        };
+        let first = digits.first().ok_or_else(no_digit)?;
+        let last_start = digits.last().ok_or_else(no_digit)?.start;
+        let last = digits.iter().find(|m| m.start == last_start).unwrap();
+        Ok(first.digit * 10 + last.digit)
+    })
+}
+
+fn calibrate(
+    input: &str,
+    matcher: &DigitMatcher,
+    bad_lines: BadLines,
+) -> Result<Calibration, NoDigit> {
+    let mut calibration = Calibration {
+        sum: 0,
+        bad_lines: Vec::new(),
+    };
+    for value in calibration_values(input, matcher) {
+        match (value, bad_lines) {
+            (Ok(value), _) => calibration.sum += value,
+            (Err(error), BadLines::Abort) => return Err(error),
+            (Err(_), BadLines::Skip) => {}
+            (Err(error), BadLines::Report) => calibration.bad_lines.push(error),
         }
     }
-    panic!()
+    Ok(calibration)
+}
+
+/// The words that stand for each digit, in one or more languages.
+#[derive(Clone)]
+struct Vocabulary {
+    words: Vec<(String, usize)>,
 }
 
-fn get_last(line: &str) -> usize {
-    for i in (0..line.len()).rev() {
-        match start_digit(&line[i..]) {
-            Some(x) => return x,
-            None => continue,
+/// Why a (word, digit) pair can't go in a vocabulary.
+#[derive(Debug, Eq, PartialEq)]
+enum VocabularyError {
+    EmptyWord { digit: usize },
+    NotADigit { word: String, digit: usize },
+}
+
+impl Vocabulary {
+    /// A vocabulary from (word, digit) pairs. Digits must be 0 to 9.
+    fn new(words: &[(&str, usize)]) -> Result<Vocabulary, VocabularyError> {
+        for (word, digit) in words {
+            if word.is_empty() {
+                return Err(VocabularyError::EmptyWord { digit: *digit });
+            }
+            if *digit >= 10 {
+                return Err(VocabularyError::NotADigit {
+                    word: String::from(*word),
+                    digit: *digit,
+                });
+            }
         }
+        Ok(Vocabulary {
+            words: words
+                .iter()
+                .map(|(word, digit)| (String::from(*word), *digit))
+                .collect(),
+        })
+    }
+
+    /// The words of `words` listed in digit order, from zero.
+    fn from_list(words: [&str; 10]) -> Result<Vocabulary, VocabularyError> {
+        Vocabulary::new(
+            &words
+                .iter()
+                .enumerate()
+                .map(|(digit, word)| (*word, digit))
+                .collect::<Vec<(&str, usize)>>(),
+        )
+    }
+
+    fn english() -> Vocabulary {
+        Vocabulary::from_list([
+            "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+        ])
+        .unwrap()
+    }
+
+    fn german() -> Vocabulary {
+        Vocabulary::from_list([
+            "null", "eins", "zwei", "drei", "vier", "fünf", "sechs", "sieben", "acht", "neun",
+        ])
+        .unwrap()
+    }
+
+    fn french() -> Vocabulary {
+        Vocabulary::from_list([
+            "zéro", "un", "deux", "trois", "quatre", "cinq", "six", "sept", "huit", "neuf",
+        ])
+        .unwrap()
+    }
+
+    fn spanish() -> Vocabulary {
+        Vocabulary::from_list([
+            "cero", "uno", "dos", "tres", "cuatro", "cinco", "seis", "siete", "ocho", "nueve",
+        ])
+        .unwrap()
+    }
+
+    /// The words of both vocabularies. Where they give the same word different
+    /// digits, `other` wins.
+    fn combine(&self, other: &Vocabulary) -> Vocabulary {
+        let mut words = self.words.clone();
+        words.extend(other.words.iter().cloned());
+        Vocabulary { words }
     }
-    panic!()
 }
 
-fn start_digit(line: &str) -> Option<usize> {
-    match line {
-        x if x.starts_with("1") || x.starts_with("one") => Some(1),
-        x if x.starts_with("2") || x.starts_with("two") => Some(2),
-        x if x.starts_with("3") || x.starts_with("three") => Some(3),
-        x if x.starts_with("4") || x.starts_with("four") => Some(4),
-        x if x.starts_with("5") || x.starts_with("five") => Some(5),
-        x if x.starts_with("6") || x.starts_with("six") => Some(6),
-        x if x.starts_with("7") || x.starts_with("seven") => Some(7),
-        x if x.starts_with("8") || x.starts_with("eight") => Some(8),
-        x if x.starts_with("9") || x.starts_with("nine") => Some(9),
-        x if x.starts_with("0") || x.starts_with("zero") => Some(0),
-        _ => None,
+/// A digit found in a line: its value and the characters it spans.
+#[derive(Debug, Eq, PartialEq)]
+struct Match {
+    digit: usize,
+    start: usize,
+    len: usize,
+}
+
+#[derive(Default)]
+struct Node {
+    next: HashMap<char, usize>,
+    fail: usize,
+    /// Every word ending here, as (length in chars, digit), longest first.
+    outputs: Vec<(usize, usize)>,
+}
+
+/// An Aho-Corasick automaton over the digit characters and the words of a
+/// vocabulary, finding every occurrence in one pass, including overlapping ones
+/// like the `eight` and `two` of `eightwo`.
+struct DigitMatcher {
+    nodes: Vec<Node>,
+}
+
+impl DigitMatcher {
+    fn new(vocabulary: &Vocabulary) -> DigitMatcher {
+        let mut nodes = vec![Node::default()];
+        let digits = (0..10).map(|digit| {
+            (
+                char::from_digit(digit as u32, 10).unwrap().to_string(),
+                digit,
+            )
+        });
+        for (word, digit) in digits.chain(vocabulary.words.iter().cloned()) {
+            let mut node = 0;
+            for c in word.chars() {
+                node = match nodes[node].next.get(&c) {
+                    Some(child) => *child,
+                    None => {
+                        nodes.push(Node::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].next.insert(c, child);
+                        child
+                    }
+                };
+            }
+            nodes[node].outputs = vec![(word.chars().count(), digit)];
+        }
+
+        // Breadth first, so a node's failure link is finished before its children's.
+        let mut queue = VecDeque::from([0]);
+        while let Some(node) = queue.pop_front() {
+            let children = nodes[node]
+                .next
+                .iter()
+                .map(|(c, child)| (*c, *child))
+                .collect::<Vec<(char, usize)>>();
+            for (c, child) in children {
+                let fail = match node {
+                    0 => 0,
+                    _ => DigitMatcher::advance(&nodes, nodes[node].fail, c),
+                };
+                nodes[child].fail = fail;
+                let inherited = nodes[fail].outputs.clone();
+                nodes[child].outputs.extend(inherited);
+                queue.push_back(child);
+            }
+        }
+
+        DigitMatcher { nodes }
+    }
+
+    fn advance(nodes: &[Node], mut node: usize, c: char) -> usize {
+        loop {
+            if let Some(next) = nodes[node].next.get(&c) {
+                return *next;
+            }
+            if node == 0 {
+                return 0;
+            }
+            node = nodes[node].fail;
+        }
+    }
+
+    /// Every digit in `line`, ordered by where it starts. The line is walked a char
+    /// at a time, so positions are in chars rather than bytes.
+    fn find(&self, line: &str) -> Vec<Match> {
+        let mut matches = Vec::new();
+        let mut node = 0;
+        for (idx, c) in line.chars().enumerate() {
+            node = DigitMatcher::advance(&self.nodes, node, c);
+            for (len, digit) in self.nodes[node].outputs.iter() {
+                matches.push(Match {
+                    digit: *digit,
+                    start: idx + 1 - len,
+                    len: *len,
+                });
+            }
+        }
+        matches.sort_by_key(|m| (m.start, std::cmp::Reverse(m.len)));
+        matches
     }
 }
 
@@ -92,4 +294,181 @@ mod tests {
         let result = part_2(INPUT);
         assert_eq!(result, 54265);
     }
+
+    fn digits(matcher: &DigitMatcher, line: &str) -> Vec<usize> {
+        matcher.find(line).iter().map(|m| m.digit).collect()
+    }
+
+    #[test]
+    fn overlapping_words() {
+        let matcher = DigitMatcher::new(&Vocabulary::english());
+        assert_eq!(digits(&matcher, "eightwo"), vec![8, 2]);
+        assert_eq!(digits(&matcher, "xtwone3four"), vec![2, 1, 3, 4]);
+        assert_eq!(digits(&matcher, "oneight0"), vec![1, 8, 0]);
+        assert_eq!(digits(&matcher, "sevenine"), vec![7, 9]);
+        assert_eq!(
+            matcher.find("zoneight"),
+            vec![
+                Match {
+                    digit: 1,
+                    start: 1,
+                    len: 3
+                },
+                Match {
+                    digit: 8,
+                    start: 3,
+                    len: 5
+                },
+            ]
+        );
+        assert!(matcher.find("abc").is_empty());
+    }
+
+    #[test]
+    fn other_languages() {
+        let german = DigitMatcher::new(&Vocabulary::german());
+        assert_eq!(
+            digits(&german, "achtzweifünf7neunull"),
+            vec![8, 2, 5, 7, 9, 0]
+        );
+        let french = DigitMatcher::new(&Vocabulary::french());
+        assert_eq!(digits(&french, "zérodeuxseptrois"), vec![0, 2, 7, 3]);
+        let spanish = DigitMatcher::new(&Vocabulary::spanish());
+        assert_eq!(digits(&spanish, "docetresiete"), vec![3, 7]);
+        let all = DigitMatcher::new(
+            &Vocabulary::english()
+                .combine(&Vocabulary::german())
+                .combine(&Vocabulary::french())
+                .combine(&Vocabulary::spanish()),
+        );
+        assert_eq!(digits(&all, "ochofünfsix"), vec![8, 5, 6]);
+        // The French "un" hides inside the German "neun".
+        assert_eq!(digits(&all, "neuneuf"), vec![9, 1, 9]);
+    }
+
+    #[test]
+    fn custom_vocabulary() {
+        let vocabulary = Vocabulary::new(&[("i", 1), ("ii", 2), ("iii", 3), ("v", 5)]).unwrap();
+        let matcher = DigitMatcher::new(&vocabulary);
+        assert_eq!(digits(&matcher, "iiv"), vec![2, 1, 1, 5]);
+        let overridden = vocabulary.combine(&Vocabulary::new(&[("v", 4)]).unwrap());
+        assert_eq!(digits(&DigitMatcher::new(&overridden), "v"), vec![4]);
+    }
+
+    #[test]
+    fn vocabulary_errors() {
+        assert_eq!(
+            Vocabulary::new(&[("one", 1), ("", 2)]).err(),
+            Some(VocabularyError::EmptyWord { digit: 2 })
+        );
+        assert_eq!(
+            Vocabulary::new(&[("ten", 10)]).err(),
+            Some(VocabularyError::NotADigit {
+                word: String::from("ten"),
+                digit: 10
+            })
+        );
+        let mut words = ["x"; 10];
+        words[3] = "";
+        assert_eq!(
+            Vocabulary::from_list(words).err(),
+            Some(VocabularyError::EmptyWord { digit: 3 })
+        );
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let vocabulary = Vocabulary::english().combine(&Vocabulary::spanish());
+        let matcher = DigitMatcher::new(&vocabulary);
+        let mut words = vocabulary.words.clone();
+        words.extend((0..10).map(|digit| (digit.to_string(), digit)));
+        for line in SAMPLE_2
+            .lines()
+            .chain(INPUT.lines())
+            .chain(["doseisieteightwone"])
+        {
+            let mut expected = Vec::new();
+            for start in 0..line.len() {
+                let mut found = words
+                    .iter()
+                    .filter(|(word, _)| line[start..].starts_with(word.as_str()))
+                    .map(|(word, digit)| (std::cmp::Reverse(word.len()), *digit))
+                    .collect::<Vec<_>>();
+                found.sort();
+                expected.extend(found.into_iter().map(|(_, digit)| digit));
+            }
+            assert_eq!(digits(&matcher, line), expected);
+        }
+    }
+
+    #[test]
+    fn unicode_lines() {
+        let matcher = DigitMatcher::new(&Vocabulary::english());
+        let input = "ñeightwo\n€3 für zwei ✓\n日本one語\nnaïve\n";
+        let values = calibration_values(input, &matcher)
+            .map(|value| value.map_err(|e| e.line))
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![Ok(82), Ok(33), Ok(11), Err(4)]);
+        assert_eq!(
+            calibration_values("\n", &matcher).next(),
+            Some(Err(NoDigit {
+                line: 1,
+                text: String::new()
+            }))
+        );
+
+        let german = DigitMatcher::new(&Vocabulary::german());
+        assert_eq!(
+            calibration_values("größe fünfundachtzig", &german).next(),
+            Some(Ok(58))
+        );
+        assert_eq!(
+            german.find("ä1"),
+            vec![Match {
+                digit: 1,
+                start: 1,
+                len: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn bad_lines() {
+        let matcher = DigitMatcher::new(&Vocabulary::english());
+        let input = "two1nine\nnöthing\nabcone2threexyz\n\n7";
+        let error = NoDigit {
+            line: 2,
+            text: String::from("nöthing"),
+        };
+        assert_eq!(error.to_string(), "no digit found on line 2: \"nöthing\"");
+        let source: &dyn std::error::Error = &error;
+        assert!(source.source().is_none());
+        assert_eq!(
+            calibrate(input, &matcher, BadLines::Abort).err(),
+            Some(error)
+        );
+
+        let skipped = calibrate(input, &matcher, BadLines::Skip).unwrap();
+        assert_eq!(skipped.sum, 29 + 13 + 77);
+        assert!(skipped.bad_lines.is_empty());
+
+        let reported = calibrate(input, &matcher, BadLines::Report).unwrap();
+        assert_eq!(reported.sum, 29 + 13 + 77);
+        assert_eq!(
+            reported
+                .bad_lines
+                .iter()
+                .map(|e| e.line)
+                .collect::<Vec<usize>>(),
+            vec![2, 4]
+        );
+    }
+
+    #[test]
+    fn ties_prefer_longer_words() {
+        let matcher = DigitMatcher::new(&Vocabulary::new(&[("i", 1), ("ix", 9)]).unwrap());
+        let values =
+            calibration_values("ix\nixi\niix", &matcher).collect::<Result<Vec<usize>, NoDigit>>();
+        assert_eq!(values, Ok(vec![99, 91, 19]));
+    }
 }
@@ -1,6 +1,4 @@
-use std::collections::HashSet;
-
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 enum Dir {
     Up,
     Down,
@@ -46,34 +44,60 @@ impl Tile {
         }
     }
 
-    fn next_dir(&self, into_dir: &Dir) -> Dir {
+    fn next_dir(&self, into_dir: &Dir) -> Option<Dir> {
         match into_dir {
             Dir::Up => match self {
-                Tile::NS => Dir::Up,
-                Tile::SW => Dir::Left,
-                Tile::SE => Dir::Right,
-                _ => panic!(),
+                Tile::NS => Some(Dir::Up),
+                Tile::SW => Some(Dir::Left),
+                Tile::SE => Some(Dir::Right),
+                _ => None,
             },
             Dir::Down => match self {
-                Tile::NS => Dir::Down,
-                Tile::NE => Dir::Right,
-                Tile::NW => Dir::Left,
-                _ => panic!(),
+                Tile::NS => Some(Dir::Down),
+                Tile::NE => Some(Dir::Right),
+                Tile::NW => Some(Dir::Left),
+                _ => None,
             },
             Dir::Left => match self {
-                Tile::WE => Dir::Left,
-                Tile::NE => Dir::Up,
-                Tile::SE => Dir::Down,
-                _ => panic!(),
+                Tile::WE => Some(Dir::Left),
+                Tile::NE => Some(Dir::Up),
+                Tile::SE => Some(Dir::Down),
+                _ => None,
             },
             Dir::Right => match self {
-                Tile::WE => Dir::Right,
-                Tile::NW => Dir::Up,
-                Tile::SW => Dir::Down,
-                _ => panic!(),
+                Tile::WE => Some(Dir::Right),
+                Tile::NW => Some(Dir::Up),
+                Tile::SW => Some(Dir::Down),
+                _ => None,
             },
         }
     }
+
+    /// Whether a pipe on this tile can be entered while travelling in `into_dir`.
+    fn accepts(&self, into_dir: &Dir) -> bool {
+        self.next_dir(into_dir).is_some()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum MapError {
+    NoStart,
+    MultipleStarts(Vec<(usize, usize)>),
+    /// The start doesn't connect to exactly two neighbours, so its pipe can't be inferred.
+    AmbiguousStart {
+        pos: (usize, usize),
+        connections: Vec<Dir>,
+    },
+    /// The loop reaches a tile at `pos` that doesn't connect back to where it came from.
+    BrokenLoop {
+        pos: (usize, usize),
+        into_dir: Dir,
+    },
+    /// The pipe at `pos` points off the edge of the grid.
+    LeavesGrid {
+        pos: (usize, usize),
+        dir: Dir,
+    },
 }
 
 struct Map {
@@ -90,81 +114,86 @@ impl Map {
         }
     }
 
-    fn get(&self, (row_idx, col_idx): (usize, usize)) -> &Tile {
-        &self.tiles[row_idx][col_idx]
+    /// The position one step from `pos` in `dir`, if there is a tile there. Rows
+    /// may differ in length, so this checks against the row being moved into.
+    fn neighbour(&self, (row_idx, col_idx): (usize, usize), dir: Dir) -> Option<(usize, usize)> {
+        let next_pos = match dir {
+            Dir::Up if row_idx == 0 => return None,
+            Dir::Left if col_idx == 0 => return None,
+            _ => dir.move_pos((row_idx, col_idx)),
+        };
+        self.get(next_pos).map(|_| next_pos)
+    }
+
+    fn get(&self, (row_idx, col_idx): (usize, usize)) -> Option<&Tile> {
+        self.tiles.get(row_idx).and_then(|row| row.get(col_idx))
     }
 
-    fn start(&self) -> ((usize, usize), Dir, Tile) {
-        let pos = self
+    fn start(&self) -> Result<((usize, usize), Dir, Tile), MapError> {
+        let starts = self
             .tiles
             .iter()
-            .map(|row| {
+            .enumerate()
+            .flat_map(|(row_idx, row)| {
                 row.iter()
                     .enumerate()
-                    .find(|(_, t)| matches!(**t, Tile::S))
-                    .map(|(col_idx, _)| col_idx)
+                    .filter(|(_, t)| matches!(**t, Tile::S))
+                    .map(move |(col_idx, _)| (row_idx, col_idx))
             })
-            .enumerate()
-            .find(|(_, opt_col_idx)| opt_col_idx.is_some())
-            .map(|(row_idx, opt_col_idx)| (row_idx, opt_col_idx.unwrap()))
-            .unwrap();
-
-        let mut potential_dirs = HashSet::new();
-        if pos.0 > 0
-            && matches!(
-                self.get(Dir::Up.move_pos(pos)),
-                Tile::SE | Tile::SW | Tile::NS
-            )
-        {
-            potential_dirs.insert(Dir::Up);
-        }
-        if let Tile::NS | Tile::NE | Tile::NW = self.get(Dir::Down.move_pos(pos)) {
-            potential_dirs.insert(Dir::Down);
-        }
-        if let Tile::WE | Tile::NW | Tile::SW = self.get(Dir::Right.move_pos(pos)) {
-            potential_dirs.insert(Dir::Right);
-        }
-        if pos.1 > 0
-            && matches!(
-                self.get(Dir::Left.move_pos(pos)),
-                Tile::WE | Tile::NE | Tile::SE
-            )
-        {
-            potential_dirs.insert(Dir::Left);
-        }
-        assert_eq!(potential_dirs.len(), 2);
-        if potential_dirs.contains(&Dir::Up) {
-            if potential_dirs.contains(&Dir::Left) {
-                return (pos, Dir::Up, Tile::NW);
-            } else if potential_dirs.contains(&Dir::Right) {
-                return (pos, Dir::Up, Tile::NE);
-            } else if potential_dirs.contains(&Dir::Down) {
-                return (pos, Dir::Up, Tile::NS);
-            }
-        } else if potential_dirs.contains(&Dir::Down) {
-            if potential_dirs.contains(&Dir::Left) {
-                return (pos, Dir::Down, Tile::SW);
-            } else if potential_dirs.contains(&Dir::Right) {
-                return (pos, Dir::Down, Tile::SE);
-            }
-        }
-        return (pos, Dir::Left, Tile::WE);
+            .collect::<Vec<(usize, usize)>>();
+        let pos = match starts[..] {
+            [] => return Err(MapError::NoStart),
+            [pos] => pos,
+            _ => return Err(MapError::MultipleStarts(starts)),
+        };
+
+        let connections = [Dir::Up, Dir::Down, Dir::Left, Dir::Right]
+            .into_iter()
+            .filter(|dir| {
+                self.neighbour(pos, *dir)
+                    .and_then(|next_pos| self.get(next_pos))
+                    .is_some_and(|tile| tile.accepts(dir))
+            })
+            .collect::<Vec<Dir>>();
+
+        let tile = match connections[..] {
+            [Dir::Up, Dir::Down] => Tile::NS,
+            [Dir::Up, Dir::Left] => Tile::NW,
+            [Dir::Up, Dir::Right] => Tile::NE,
+            [Dir::Down, Dir::Left] => Tile::SW,
+            [Dir::Down, Dir::Right] => Tile::SE,
+            [Dir::Left, Dir::Right] => Tile::WE,
+            _ => return Err(MapError::AmbiguousStart { pos, connections }),
+        };
+        Ok((pos, connections[0], tile))
     }
 
-    fn traverse<F>(&self, mut f: F)
+    fn traverse<F>(&self, mut f: F) -> Result<(), MapError>
     where
         F: FnMut((usize, usize)),
     {
-        let (start_pos, start_dir, _) = self.start();
-        let (mut pos, mut into_dir) = (start_dir.move_pos(start_pos), start_dir);
+        let (start_pos, start_dir, _) = self.start()?;
+        let step = |pos: (usize, usize), dir: Dir| {
+            self.neighbour(pos, dir)
+                .ok_or(MapError::LeavesGrid { pos, dir })
+        };
+        let (mut pos, mut into_dir) = (step(start_pos, start_dir)?, start_dir);
 
         while pos != start_pos {
             f(pos);
-            let tile = self.get(pos);
-            let next_dir = tile.next_dir(&into_dir);
-            let next_pos = next_dir.move_pos(pos);
-            (pos, into_dir) = (next_pos, next_dir);
+            let next_dir = self
+                .get(pos)
+                .and_then(|tile| tile.next_dir(&into_dir))
+                .ok_or(MapError::BrokenLoop { pos, into_dir })?;
+            (pos, into_dir) = (step(pos, next_dir)?, next_dir);
         }
+        Ok(())
+    }
+
+    /// Checks that the map has a single start whose pipe can be inferred, and
+    /// that following the pipes from it forms a closed loop inside the grid.
+    fn validate(&self) -> Result<(), MapError> {
+        self.traverse(|_| ())
     }
 }
 
@@ -172,7 +201,7 @@ fn part_1(input: &str) -> usize {
     let map = Map::parse_input(input);
     let mut steps = 1;
 
-    map.traverse(|_| steps += 1);
+    map.traverse(|_| steps += 1).unwrap();
 
     return steps / 2;
 }
@@ -186,7 +215,7 @@ enum State {
 
 fn part_2(input: &str) -> usize {
     let map = Map::parse_input(input);
-    let (start_pos, _, start_tile) = map.start();
+    let (start_pos, _, start_tile) = map.start().unwrap();
 
     // Mark loop
     let mut markings = Vec::new();
@@ -198,7 +227,7 @@ fn part_2(input: &str) -> usize {
         markings.push(line);
     }
     markings[start_pos.0][start_pos.1] = true;
-    map.traverse(|pos| markings[pos.0][pos.1] = true);
+    map.traverse(|pos| markings[pos.0][pos.1] = true).unwrap();
 
     // Find inner
     let mut inner_ground = 0;
@@ -310,4 +339,93 @@ mod tests {
         let result = part_2(INPUT);
         dbg!(result);
     }
+
+    #[test]
+    fn validate_samples() {
+        for sample in [SAMPLE_1, SAMPLE_2, SAMPLE_3, SAMPLE_4, SAMPLE_5] {
+            assert_eq!(Map::parse_input(sample).validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn validate_start_on_edges() {
+        let map = Map::parse_input("F-7\n|.|\nL-S");
+        assert_eq!(map.validate(), Ok(()));
+        assert_eq!(map.start().unwrap().0, (2, 2));
+
+        let map = Map::parse_input("S-7\n|.|\nL-J");
+        assert_eq!(map.validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_no_start() {
+        let map = Map::parse_input("F-7\n|.|\nL-J");
+        assert_eq!(map.validate(), Err(MapError::NoStart));
+    }
+
+    #[test]
+    fn validate_multiple_starts() {
+        let map = Map::parse_input("S-7\n|.|\nL-S");
+        assert_eq!(
+            map.validate(),
+            Err(MapError::MultipleStarts(vec![(0, 0), (2, 2)]))
+        );
+    }
+
+    #[test]
+    fn validate_ambiguous_start() {
+        let map = Map::parse_input(".|.\n-S-\n.|.");
+        assert_eq!(
+            map.validate(),
+            Err(MapError::AmbiguousStart {
+                pos: (1, 1),
+                connections: vec![Dir::Up, Dir::Down, Dir::Left, Dir::Right],
+            })
+        );
+
+        let map = Map::parse_input("...\n.S-\n...");
+        assert_eq!(
+            map.validate(),
+            Err(MapError::AmbiguousStart {
+                pos: (1, 1),
+                connections: vec![Dir::Right],
+            })
+        );
+    }
+
+    #[test]
+    fn validate_broken_loop() {
+        let map = Map::parse_input("S-7\n|.|\nL.J");
+        assert_eq!(
+            map.validate(),
+            Err(MapError::BrokenLoop {
+                pos: (2, 1),
+                into_dir: Dir::Right,
+            })
+        );
+    }
+
+    #[test]
+    fn validate_leaves_grid() {
+        let map = Map::parse_input("S-7\n|.|\nL-7");
+        assert_eq!(
+            map.validate(),
+            Err(MapError::LeavesGrid {
+                pos: (2, 2),
+                dir: Dir::Down,
+            })
+        );
+    }
+
+    #[test]
+    fn validate_ragged_grid() {
+        let map = Map::parse_input("S-7\n|.\nL-J");
+        assert_eq!(
+            map.validate(),
+            Err(MapError::LeavesGrid {
+                pos: (2, 2),
+                dir: Dir::Up,
+            })
+        );
+    }
 }
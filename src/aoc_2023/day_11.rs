@@ -1,4 +1,5 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 
 type Point = (usize, usize);
 
@@ -43,37 +44,295 @@ impl Image {
     }
 }
 
-fn part_x(input: &str, dist: usize) -> usize {
-    let image = Image::parse_input(input);
-    let mut sum = 0;
-    for (g1_idx, g1) in image.galaxies.iter().enumerate() {
-        for (g2_idx, g2) in image.galaxies.iter().enumerate() {
-            if g1_idx == g2_idx {
-                continue;
+impl Image {
+    /// Maps each coordinate along one axis to its position after expansion, where the
+    /// empty line at index `idx` grows to `dist(idx)` lines.
+    fn expand_axis<F>(coords: impl Iterator<Item = usize>, empty: &[usize], dist: F) -> Vec<usize>
+    where
+        F: Fn(usize) -> usize,
+    {
+        let mut grown_before = vec![0];
+        for idx in empty {
+            grown_before.push(grown_before.last().unwrap() + dist(*idx));
+        }
+        coords
+            .map(|coord| {
+                let num_empty_before = empty.binary_search(&coord).unwrap_err();
+                coord - num_empty_before + grown_before[num_empty_before]
+            })
+            .collect()
+    }
+
+    /// Galaxy coordinates, in the same order as `galaxies`, after each empty row
+    /// `idx` grows to `row_dist(idx)` rows and each empty column to `col_dist(idx)`.
+    fn expanded_galaxies_with<R, C>(&self, row_dist: R, col_dist: C) -> Vec<Point>
+    where
+        R: Fn(usize) -> usize,
+        C: Fn(usize) -> usize,
+    {
+        let rows = Image::expand_axis(
+            self.galaxies.iter().map(|g| g.0),
+            &self.empty_rows,
+            row_dist,
+        );
+        let cols = Image::expand_axis(
+            self.galaxies.iter().map(|g| g.1),
+            &self.empty_cols,
+            col_dist,
+        );
+        rows.into_iter().zip(cols).collect()
+    }
+
+    fn expanded_galaxies(&self, dist: usize) -> Vec<Point> {
+        self.expanded_galaxies_with(|_| dist, |_| dist)
+    }
+
+    fn sum_of_distances_pairwise(&self, dist: usize) -> usize {
+        let mut sum = 0;
+        for (g1_idx, g1) in self.galaxies.iter().enumerate() {
+            for (g2_idx, g2) in self.galaxies.iter().enumerate() {
+                if g1_idx == g2_idx {
+                    continue;
+                }
+                let lowest_empty_row_idx =
+                    self.empty_rows.binary_search(&g1.0.min(g2.0)).unwrap_err();
+                let highest_empty_row_idx =
+                    self.empty_rows.binary_search(&g1.0.max(g2.0)).unwrap_err();
+                let num_empty_rows_between =
+                    (highest_empty_row_idx - lowest_empty_row_idx) * (dist - 1);
+                let lowest_empty_col_idx =
+                    self.empty_cols.binary_search(&g1.1.min(g2.1)).unwrap_err();
+                let highest_empty_col_idx =
+                    self.empty_cols.binary_search(&g1.1.max(g2.1)).unwrap_err();
+                let num_empty_cols_between =
+                    (highest_empty_col_idx - lowest_empty_col_idx) * (dist - 1);
+
+                sum += (g1.0.max(g2.0) - g1.0.min(g2.0) + num_empty_rows_between)
+                    + (g1.1.max(g2.1) - g1.1.min(g2.1) + num_empty_cols_between);
             }
-            let lowest_empty_row_idx = image.empty_rows.binary_search(&g1.0.min(g2.0)).unwrap_err();
-            let highest_empty_row_idx =
-                image.empty_rows.binary_search(&g1.0.max(g2.0)).unwrap_err();
-            let num_empty_rows_between =
-                (highest_empty_row_idx - lowest_empty_row_idx) * (dist - 1);
-            let lowest_empty_col_idx = image.empty_cols.binary_search(&g1.1.min(g2.1)).unwrap_err();
-            let highest_empty_col_idx =
-                image.empty_cols.binary_search(&g1.1.max(g2.1)).unwrap_err();
-            let num_empty_cols_between =
-                (highest_empty_col_idx - lowest_empty_col_idx) * (dist - 1);
-
-            sum += (g1.0.max(g2.0) - g1.0.min(g2.0) + num_empty_rows_between)
-                + (g1.1.max(g2.1) - g1.1.min(g2.1) + num_empty_cols_between);
         }
+
+        return sum / 2;
+    }
+
+    /// Same result as `sum_of_distances_pairwise`, but in O(n log n): the Manhattan
+    /// distance splits into independent row and column sums, and each of those is a
+    /// prefix sum over the sorted coordinates.
+    fn sum_of_distances_with<R, C>(&self, row_dist: R, col_dist: C) -> usize
+    where
+        R: Fn(usize) -> usize,
+        C: Fn(usize) -> usize,
+    {
+        let (rows, cols) = self
+            .expanded_galaxies_with(row_dist, col_dist)
+            .into_iter()
+            .unzip();
+        sum_of_1d_distances(rows) + sum_of_1d_distances(cols)
     }
 
-    return sum / 2;
+    fn sum_of_distances(&self, dist: usize) -> usize {
+        self.sum_of_distances_with(|_| dist, |_| dist)
+    }
+}
+
+/// Expansion factors for individual empty rows and columns, falling back to
+/// `default` for any line that isn't listed.
+struct Weights {
+    default: usize,
+    rows: HashMap<usize, usize>,
+    cols: HashMap<usize, usize>,
+}
+
+#[derive(Debug)]
+enum WeightsError {
+    Io(std::io::Error),
+    /// A line that isn't `default N`, `row I N` or `col I N`. `line` counts from 1.
+    BadLine {
+        line: usize,
+        text: String,
+    },
+}
+
+impl Weights {
+    /// Parses one weight per line, e.g.
+    ///
+    /// ```text
+    /// default 2
+    /// row 3 10
+    /// col 7 1
+    /// ```
+    fn parse_input(input: &str) -> Result<Weights, WeightsError> {
+        let mut weights = Weights {
+            default: 2,
+            rows: HashMap::new(),
+            cols: HashMap::new(),
+        };
+        for (idx, line) in input.lines().enumerate() {
+            let bad_line = || WeightsError::BadLine {
+                line: idx + 1,
+                text: String::from(line),
+            };
+            let number = |s: &str| s.parse::<usize>().map_err(|_| bad_line());
+            let parts = line.split_whitespace().collect::<Vec<&str>>();
+            match parts[..] {
+                [] => {}
+                ["default", dist] => weights.default = number(dist)?,
+                ["row", idx, dist] => {
+                    weights.rows.insert(number(idx)?, number(dist)?);
+                }
+                ["col", idx, dist] => {
+                    weights.cols.insert(number(idx)?, number(dist)?);
+                }
+                _ => return Err(bad_line()),
+            }
+        }
+        Ok(weights)
+    }
+
+    /// Every empty line grows to `dist` lines.
+    fn uniform(dist: usize) -> Weights {
+        Weights {
+            default: dist,
+            rows: HashMap::new(),
+            cols: HashMap::new(),
+        }
+    }
+
+    fn read_file(path: &str) -> Result<Weights, WeightsError> {
+        let input = fs::read_to_string(path).map_err(WeightsError::Io)?;
+        Weights::parse_input(&input)
+    }
+
+    fn row(&self, idx: usize) -> usize {
+        *self.rows.get(&idx).unwrap_or(&self.default)
+    }
+
+    fn col(&self, idx: usize) -> usize {
+        *self.cols.get(&idx).unwrap_or(&self.default)
+    }
+}
+
+impl Image {
+    fn expanded_galaxies_weighted(&self, weights: &Weights) -> Vec<Point> {
+        self.expanded_galaxies_with(|idx| weights.row(idx), |idx| weights.col(idx))
+    }
+
+    fn sum_of_distances_weighted(&self, weights: &Weights) -> usize {
+        self.sum_of_distances_with(|idx| weights.row(idx), |idx| weights.col(idx))
+    }
+}
+
+fn manhattan(a: Point, b: Point) -> usize {
+    a.0.abs_diff(b.0) + a.1.abs_diff(b.1)
+}
+
+/// Queries on individual galaxies. Galaxies are numbered from 1 in reading order,
+/// as in the puzzle, and every distance is measured after expanding by `weights`.
+/// A number that isn't a galaxy gives `None`.
+impl Image {
+    fn galaxy_idx(&self, number: usize) -> Option<usize> {
+        number
+            .checked_sub(1)
+            .filter(|idx| *idx < self.galaxies.len())
+    }
+
+    fn distance(&self, g1: usize, g2: usize, weights: &Weights) -> Option<usize> {
+        let (idx1, idx2) = (self.galaxy_idx(g1)?, self.galaxy_idx(g2)?);
+        let pair = [self.galaxies[idx1], self.galaxies[idx2]];
+        let rows = Image::expand_axis(pair.iter().map(|g| g.0), &self.empty_rows, |idx| {
+            weights.row(idx)
+        });
+        let cols = Image::expand_axis(pair.iter().map(|g| g.1), &self.empty_cols, |idx| {
+            weights.col(idx)
+        });
+        Some(rows[0].abs_diff(rows[1]) + cols[0].abs_diff(cols[1]))
+    }
+
+    /// The `k` galaxies closest to galaxy `g`, as (number, distance), closest first
+    /// and ties broken by number.
+    fn nearest(&self, g: usize, k: usize, weights: &Weights) -> Option<Vec<(usize, usize)>> {
+        let g_idx = self.galaxy_idx(g)?;
+        let expanded = self.expanded_galaxies_weighted(weights);
+        let mut others = expanded
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| *idx != g_idx)
+            .map(|(idx, p)| (idx + 1, manhattan(expanded[g_idx], *p)))
+            .collect::<Vec<(usize, usize)>>();
+        others.sort_by_key(|(number, d)| (*d, *number));
+        others.truncate(k);
+        Some(others)
+    }
+
+    /// The two galaxies furthest apart, as (number, number, distance).
+    ///
+    /// Manhattan distance is the larger of |Δ(row + col)| and |Δ(row - col)|, so
+    /// the answer comes from the extremes of those two sums in a single pass.
+    fn farthest_pair(&self, weights: &Weights) -> Option<(usize, usize, usize)> {
+        let expanded = self.expanded_galaxies_weighted(weights);
+        if expanded.len() < 2 {
+            return None;
+        }
+        let keys: [fn(&Point) -> i64; 2] =
+            [|p| p.0 as i64 + p.1 as i64, |p| p.0 as i64 - p.1 as i64];
+        keys.iter()
+            .map(|key| {
+                let min = (0..expanded.len())
+                    .min_by_key(|idx| key(&expanded[*idx]))
+                    .unwrap();
+                let max = (0..expanded.len())
+                    .max_by_key(|idx| key(&expanded[*idx]))
+                    .unwrap();
+                (
+                    min.min(max) + 1,
+                    min.max(max) + 1,
+                    manhattan(expanded[min], expanded[max]),
+                )
+            })
+            .max_by_key(|(_, _, d)| *d)
+    }
+
+    /// Every pairwise distance as CSV, with galaxy numbers along the first row
+    /// and column.
+    fn distance_matrix_csv(&self, weights: &Weights) -> String {
+        let expanded = self.expanded_galaxies_weighted(weights);
+        let header = (1..=expanded.len())
+            .map(|number| number.to_string())
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut csv = format!(",{}\n", header);
+        for (idx, from) in expanded.iter().enumerate() {
+            let row = expanded
+                .iter()
+                .map(|to| manhattan(*from, *to).to_string())
+                .collect::<Vec<String>>()
+                .join(",");
+            csv.push_str(&format!("{},{}\n", idx + 1, row));
+        }
+        csv
+    }
+}
+
+fn sum_of_1d_distances(mut coords: Vec<usize>) -> usize {
+    coords.sort_unstable();
+    let mut prefix_sum = 0;
+    let mut sum = 0;
+    for (idx, coord) in coords.into_iter().enumerate() {
+        sum += coord * idx - prefix_sum;
+        prefix_sum += coord;
+    }
+    sum
+}
+
+fn part_x(input: &str, dist: usize) -> usize {
+    Image::parse_input(input).sum_of_distances(dist)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::aoc_2023::data::day_11::*;
     use crate::aoc_2023::day_11::*;
+    use crate::aoc_2023::Rng;
 
     #[test]
     fn part_1_sample() {
@@ -104,4 +363,247 @@ mod tests {
         let result = part_x(INPUT, 1000000);
         assert_eq!(result, 904633799472);
     }
+
+    fn random_image(rng: &mut Rng, num_rows: usize, num_cols: usize) -> String {
+        (0..num_rows)
+            .map(|_| {
+                (0..num_cols)
+                    .map(|_| if rng.below(7) == 0 { '#' } else { '.' })
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn sum_of_distances_matches_pairwise_sample() {
+        let image = Image::parse_input(SAMPLE);
+        for dist in [1, 2, 10, 100, 1000000] {
+            assert_eq!(
+                image.sum_of_distances(dist),
+                image.sum_of_distances_pairwise(dist)
+            );
+        }
+    }
+
+    #[test]
+    fn sum_of_distances_matches_pairwise_random() {
+        let mut rng = Rng::new(0x2023_1211);
+        for size in 1..30 {
+            let image = Image::parse_input(&random_image(&mut rng, size, size + 3));
+            for dist in [1, 2, 7, 1000000] {
+                assert_eq!(
+                    image.sum_of_distances(dist),
+                    image.sum_of_distances_pairwise(dist)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn expanded_galaxies_sample() {
+        let image = Image::parse_input(SAMPLE);
+        let expanded = image.expanded_galaxies(2);
+        assert_eq!(expanded[0], (0, 4));
+        assert_eq!(expanded[1], (1, 9));
+        assert_eq!(expanded[8], (11, 5));
+        assert_eq!(image.expanded_galaxies(1), image.galaxies);
+    }
+
+    #[test]
+    fn sum_of_distances_separate_row_and_col_factors() {
+        let image = Image::parse_input(SAMPLE);
+        let rows_only = image.sum_of_distances_with(|_| 10, |_| 1);
+        let cols_only = image.sum_of_distances_with(|_| 1, |_| 10);
+        let unexpanded = image.sum_of_distances(1);
+        assert_eq!(rows_only + cols_only - unexpanded, part_x(SAMPLE, 10));
+    }
+
+    #[test]
+    fn sum_of_distances_weighted() {
+        let image = Image::parse_input(SAMPLE);
+        let weights = Weights::parse_input("default 100\n").unwrap();
+        assert_eq!(image.sum_of_distances_weighted(&weights), 8410);
+
+        // Empty rows are 3 and 7, empty columns are 2, 5 and 8.
+        let weights = Weights::parse_input("default 1\nrow 3 4\ncol 8 3\n").unwrap();
+        let expanded = image.expanded_galaxies_weighted(&weights);
+        assert_eq!(expanded[5], (9, 11));
+        assert_eq!(expanded[7], (12, 0));
+        assert_eq!(
+            image.sum_of_distances_weighted(&weights),
+            image.sum_of_distances_with(
+                |idx| if idx == 3 { 4 } else { 1 },
+                |idx| if idx == 8 { 3 } else { 1 }
+            )
+        );
+    }
+
+    #[test]
+    fn weights_read_file() {
+        let path = std::env::temp_dir().join(format!(
+            "day_11_weights_read_file_{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, "default 10\n").unwrap();
+        let weights = Weights::read_file(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        let image = Image::parse_input(SAMPLE);
+        assert_eq!(image.sum_of_distances_weighted(&weights.unwrap()), 1030);
+
+        assert!(matches!(
+            Weights::read_file(path.to_str().unwrap()),
+            Err(WeightsError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn weights_bad_lines() {
+        let bad_line = |input: &str| match Weights::parse_input(input) {
+            Err(WeightsError::BadLine { line, text }) => Some((line, text)),
+            _ => None,
+        };
+        assert_eq!(
+            bad_line("default 2\n\nrow 3\n"),
+            Some((3, String::from("row 3")))
+        );
+        assert_eq!(bad_line("col x 2"), Some((1, String::from("col x 2"))));
+        assert_eq!(
+            bad_line("row 1 2\ndefault -1"),
+            Some((2, String::from("default -1")))
+        );
+        assert_eq!(
+            bad_line("diagonal 1 2"),
+            Some((1, String::from("diagonal 1 2")))
+        );
+        assert!(Weights::parse_input("\n  \nrow 1 5\n").is_ok());
+    }
+
+    #[test]
+    fn distance_sample() {
+        let image = Image::parse_input(SAMPLE);
+        assert_eq!(image.distance(5, 9, &Weights::uniform(2)), Some(9));
+        assert_eq!(image.distance(1, 7, &Weights::uniform(2)), Some(15));
+        assert_eq!(image.distance(3, 6, &Weights::uniform(2)), Some(17));
+        assert_eq!(image.distance(8, 9, &Weights::uniform(2)), Some(5));
+        assert_eq!(image.distance(9, 8, &Weights::uniform(2)), Some(5));
+    }
+
+    #[test]
+    fn distance_matches_expanded_galaxies() {
+        let mut rng = Rng::new(0x2023_1211_0029);
+        for size in 1..15 {
+            let image = Image::parse_input(&random_image(&mut rng, size + 2, size));
+            let weights = Weights::parse_input(&format!(
+                "default {}\nrow {} {}\ncol {} {}\n",
+                rng.below(5),
+                rng.below(size + 2),
+                rng.below(100),
+                rng.below(size),
+                rng.below(100)
+            ))
+            .unwrap();
+            let expanded = image.expanded_galaxies_weighted(&weights);
+            for (idx1, p1) in expanded.iter().enumerate() {
+                for (idx2, p2) in expanded.iter().enumerate() {
+                    assert_eq!(
+                        image.distance(idx1 + 1, idx2 + 1, &weights),
+                        Some(manhattan(*p1, *p2))
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn nearest_sample() {
+        let image = Image::parse_input(SAMPLE);
+        let weights = Weights::uniform(2);
+        assert_eq!(image.nearest(8, 2, &weights), Some(vec![(9, 5), (5, 6)]));
+        assert_eq!(image.nearest(8, 100, &weights).unwrap().len(), 8);
+        assert_eq!(image.nearest(0, 2, &weights), None);
+        assert_eq!(image.nearest(10, 2, &weights), None);
+        let weights = Weights::uniform(10);
+        assert!(image
+            .nearest(3, 8, &weights)
+            .unwrap()
+            .iter()
+            .all(|(number, d)| image.distance(3, *number, &weights) == Some(*d)));
+    }
+
+    #[test]
+    fn farthest_pair_matches_brute_force() {
+        let mut rng = Rng::new(0x2023_1129);
+        for size in 2..20 {
+            let image = Image::parse_input(&random_image(&mut rng, size, size));
+            for dist in [1, 2, 100] {
+                let weights = Weights::uniform(dist);
+                let n = image.galaxies.len();
+                let brute = (1..=n)
+                    .flat_map(|g1| (g1 + 1..=n).map(move |g2| (g1, g2)))
+                    .map(|(g1, g2)| image.distance(g1, g2, &weights).unwrap())
+                    .max();
+                assert_eq!(image.farthest_pair(&weights).map(|(_, _, d)| d), brute);
+                if let Some((g1, g2, d)) = image.farthest_pair(&weights) {
+                    assert_eq!(image.distance(g1, g2, &weights), Some(d));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn distance_matrix_csv_sample() {
+        let image = Image::parse_input(SAMPLE);
+        let csv = image.distance_matrix_csv(&Weights::uniform(2));
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[0], ",1,2,3,4,5,6,7,8,9");
+        let total = lines[1..]
+            .iter()
+            .flat_map(|line| line.split(',').skip(1))
+            .map(|d| d.parse::<usize>().unwrap())
+            .sum::<usize>();
+        assert_eq!(total, 2 * 374);
+    }
+
+    #[test]
+    fn queries_out_of_range() {
+        let image = Image::parse_input(SAMPLE);
+        let weights = Weights::uniform(2);
+        assert_eq!(image.distance(0, 1, &weights), None);
+        assert_eq!(image.distance(1, 10, &weights), None);
+        assert_eq!(image.distance(9, 9, &weights), Some(0));
+    }
+
+    #[test]
+    fn queries_honour_weights() {
+        let image = Image::parse_input(SAMPLE);
+        // Empty rows are 3 and 7, empty columns are 2, 5 and 8.
+        let weights = Weights::parse_input("default 1\nrow 3 4\ncol 8 3\n").unwrap();
+        let expanded = image.expanded_galaxies_weighted(&weights);
+        assert_eq!(
+            image.distance(6, 8, &weights),
+            Some(manhattan(expanded[5], expanded[7]))
+        );
+        assert_eq!(image.distance(6, 8, &weights), Some(14));
+        assert_eq!(image.distance(6, 8, &Weights::uniform(1)), Some(12));
+        let total = image
+            .distance_matrix_csv(&weights)
+            .lines()
+            .skip(1)
+            .flat_map(|line| {
+                line.split(',')
+                    .skip(1)
+                    .map(String::from)
+                    .collect::<Vec<String>>()
+            })
+            .map(|d| d.parse::<usize>().unwrap())
+            .sum::<usize>();
+        assert_eq!(total, 2 * image.sum_of_distances_weighted(&weights));
+        let farthest = image.farthest_pair(&weights).unwrap();
+        assert_eq!(
+            image.distance(farthest.0, farthest.1, &weights),
+            Some(farthest.2)
+        );
+    }
 }
@@ -1,8 +1,9 @@
-use std::collections::HashMap;
+use num::{BigUint, One, Zero};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::hash::Hash;
 use std::iter;
 
-#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Hash, Eq, PartialEq, Debug)]
 enum Condition {
     Operational,
     Damaged,
@@ -18,6 +19,14 @@ impl Condition {
             _ => panic!(),
         }
     }
+
+    fn to_char(self) -> char {
+        match self {
+            Condition::Damaged => '#',
+            Condition::Operational => '.',
+            Condition::Unknown => '?',
+        }
+    }
 }
 
 #[derive(Hash, Clone, Eq, PartialEq)]
@@ -38,19 +47,28 @@ impl Row {
         }
     }
 
+    /// `factor` copies of the row's springs joined by `separator`, with the damaged
+    /// groups repeated `factor` times.
+    fn unfold(&self, factor: usize, separator: Condition) -> Row {
+        let mut springs = Vec::with_capacity(factor * (self.springs.len() + 1));
+        for copy in 0..factor {
+            if copy > 0 {
+                springs.push(separator);
+            }
+            springs.extend(&self.springs);
+        }
+        Row {
+            springs,
+            damaged_groups: self.damaged_groups.repeat(factor),
+        }
+    }
+
+    fn parse_line_unfolded(line: &str, factor: usize, separator: Condition) -> Row {
+        Row::parse_line(line).unfold(factor, separator)
+    }
+
     fn parse_line_part_2(line: &str) -> Row {
-        let (springs, damaged_groups) = line.split_once(' ').unwrap();
-        let springs = iter::repeat(springs)
-            .take(5)
-            .map(String::from)
-            .collect::<Vec<String>>()
-            .join("?");
-        let damaged_groups = iter::repeat(damaged_groups)
-            .take(5)
-            .map(String::from)
-            .collect::<Vec<String>>()
-            .join(",");
-        Row::parse_line(&format!("{springs} {damaged_groups}"))
+        Row::parse_line_unfolded(line, 5, Condition::Unknown)
     }
 
     fn possibilities_r(&self, memo: &mut HashMap<Row, usize>) -> usize {
@@ -118,19 +136,653 @@ impl Row {
     }
 }
 
+/// Counts arrangements bottom-up over (spring index, group index), where
+/// `ways[i][j]` is the number of ways to place `damaged_groups[j..]` in
+/// `springs[i..]`. The buffers are kept between rows so counting a whole
+/// input only allocates when a row is larger than any seen before.
+#[derive(Default)]
+struct Arrangements {
+    ways: Vec<usize>,
+    num_groups: usize,
+    next_operational: Vec<usize>,
+    before: Vec<usize>,
+    /// Arrangements in which a group starts at each spring, and in which one ends
+    /// just before it. Their running difference is `damaged`.
+    group_starts: Vec<usize>,
+    group_ends: Vec<usize>,
+    damaged: Vec<usize>,
+}
+
+impl Arrangements {
+    fn ways(&self, spring_idx: usize, group_idx: usize) -> usize {
+        self.ways[spring_idx * (self.num_groups + 1) + group_idx]
+    }
+
+    /// If group `group_idx` can start at `spring_idx`, the index of the first spring
+    /// after it and its operational separator.
+    fn place_group(
+        &self,
+        springs: &[Condition],
+        damaged_groups: &[usize],
+        spring_idx: usize,
+        group_idx: usize,
+    ) -> Option<usize> {
+        let group_end = spring_idx + damaged_groups.get(group_idx)?;
+        if springs[spring_idx] != Condition::Operational
+            && group_end <= springs.len()
+            && self.next_operational[spring_idx] >= group_end
+            && springs.get(group_end) != Some(&Condition::Damaged)
+        {
+            Some((group_end + 1).min(springs.len()))
+        } else {
+            None
+        }
+    }
+
+    fn fill(&mut self, springs: &[Condition], damaged_groups: &[usize]) {
+        let (num_springs, num_groups) = (springs.len(), damaged_groups.len());
+        self.num_groups = num_groups;
+        self.ways.clear();
+        self.ways.resize((num_springs + 1) * (num_groups + 1), 0);
+        self.ways[num_springs * (num_groups + 1) + num_groups] = 1;
+
+        self.next_operational.clear();
+        self.next_operational.resize(num_springs + 1, num_springs);
+        for spring_idx in (0..num_springs).rev() {
+            self.next_operational[spring_idx] = match springs[spring_idx] {
+                Condition::Operational => spring_idx,
+                _ => self.next_operational[spring_idx + 1],
+            };
+        }
+
+        for spring_idx in (0..num_springs).rev() {
+            for group_idx in 0..=num_groups {
+                let mut ways = 0;
+                if springs[spring_idx] != Condition::Damaged {
+                    ways += self.ways(spring_idx + 1, group_idx);
+                }
+                if let Some(after_group) =
+                    self.place_group(springs, damaged_groups, spring_idx, group_idx)
+                {
+                    ways += self.ways(after_group, group_idx + 1);
+                }
+                self.ways[spring_idx * (num_groups + 1) + group_idx] = ways;
+            }
+        }
+    }
+
+    fn count(&mut self, row: &Row) -> usize {
+        self.fill(&row.springs, &row.damaged_groups);
+        self.ways(0, 0)
+    }
+
+    /// The `k`th arrangement of `row` in lexicographic order, where `#` sorts before
+    /// `.`. The table must already be filled for `row`.
+    ///
+    /// At each spring the arrangements starting a group there come first, so `k`
+    /// picks a branch by comparing against that branch's count.
+    fn nth(&self, row: &Row, mut k: usize) -> Option<String> {
+        if k >= self.ways(0, 0) {
+            return None;
+        }
+        let (springs, damaged_groups) = (&row.springs, &row.damaged_groups);
+        let mut arrangement = String::with_capacity(springs.len());
+        let (mut spring_idx, mut group_idx) = (0, 0);
+        while spring_idx < springs.len() {
+            if let Some(after_group) =
+                self.place_group(springs, damaged_groups, spring_idx, group_idx)
+            {
+                let ways = self.ways(after_group, group_idx + 1);
+                if k < ways {
+                    arrangement.extend(iter::repeat_n('#', damaged_groups[group_idx]));
+                    if after_group > spring_idx + damaged_groups[group_idx] {
+                        arrangement.push('.');
+                    }
+                    (spring_idx, group_idx) = (after_group, group_idx + 1);
+                    continue;
+                }
+                k -= ways;
+            }
+            arrangement.push('.');
+            spring_idx += 1;
+        }
+        Some(arrangement)
+    }
+}
+
+impl Row {
+    /// Every concrete arrangement of the row, lazily and in lexicographic order.
+    fn arrangements(&self) -> impl Iterator<Item = String> + '_ {
+        let mut table = Arrangements::default();
+        table.fill(&self.springs, &self.damaged_groups);
+        (0..table.ways(0, 0)).map(move |k| table.nth(self, k).unwrap())
+    }
+
+    /// A uniformly random arrangement, or `None` if there are none. `random_below(n)`
+    /// must return a uniformly random number in `0..n`.
+    fn random_arrangement<F>(&self, mut random_below: F) -> Option<String>
+    where
+        F: FnMut(usize) -> usize,
+    {
+        let mut table = Arrangements::default();
+        let total = table.count(self);
+        if total == 0 {
+            return None;
+        }
+        table.nth(self, random_below(total))
+    }
+}
+
+/// Where a scan over springs is relative to the damaged groups: free to start a
+/// group, inside a group with some springs of it placed, or just past the end of a
+/// group so the next spring must be operational.
+#[derive(Copy, Clone, Hash, Eq, PartialEq)]
+enum Run {
+    Free,
+    Open(usize),
+    Closed,
+}
+
+/// For a block of cells, every way to get from a run state at the start of the
+/// block to one at the end, as (groups completed, run state, count), when the block
+/// starts at group `first_group` of `damaged_groups`.
+fn transfers_from(
+    cells: &[Condition],
+    damaged_groups: &[usize],
+    first_group: usize,
+    run: Run,
+) -> Vec<(usize, Run, usize)> {
+    let group_size =
+        |completed: usize| damaged_groups[(first_group + completed) % damaged_groups.len()];
+    let mut states = HashMap::from([((0, run), 1)]);
+    for cell in cells {
+        let mut next_states = HashMap::new();
+        for ((completed, run), count) in states {
+            let mut next = |state| *next_states.entry(state).or_insert(0) += count;
+            if *cell != Condition::Damaged {
+                match run {
+                    Run::Free | Run::Closed => next((completed, Run::Free)),
+                    Run::Open(_) => {}
+                }
+            }
+            if *cell != Condition::Operational {
+                let placed = match run {
+                    Run::Free => Some(1),
+                    Run::Open(placed) => Some(placed + 1),
+                    Run::Closed => None,
+                };
+                match placed {
+                    Some(placed) if placed == group_size(completed) => {
+                        next((completed + 1, Run::Closed))
+                    }
+                    Some(placed) => next((completed, Run::Open(placed))),
+                    None => {}
+                }
+            }
+        }
+        states = next_states;
+    }
+    states
+        .into_iter()
+        .map(|((completed, run), count)| (completed, run, count))
+        .collect()
+}
+
+/// `n` choose `k`, built up one factor at a time so every partial product is itself
+/// a binomial coefficient and each division is exact.
+fn binomial(n: usize, k: usize) -> BigUint {
+    if k > n {
+        return BigUint::zero();
+    }
+    let k = k.min(n - k);
+    (1..=k).fold(BigUint::one(), |acc, i| acc * (n - k + i) / i)
+}
+
+impl Run {
+    /// An index for the run state in dense tables, less than one more than the
+    /// largest group size.
+    fn slot(self) -> usize {
+        match self {
+            Run::Free => 0,
+            Run::Closed => 1,
+            Run::Open(placed) => 1 + placed,
+        }
+    }
+}
+
+impl Row {
+    /// Counts the arrangements of `self.unfold(factor, separator)` without building it.
+    ///
+    /// The unfolded row is the same block of springs over and over, and the groups
+    /// repeat with it, so a single block's effect on the scan only depends on which
+    /// group it starts at modulo the number of groups and on the run state at its
+    /// start. Those transfers are computed once per block shape and then applied
+    /// block by block to a dense window of (groups completed, run state) counts. The
+    /// window only keeps counts that can still finish with exactly every group
+    /// placed, given the fewest and most groups the remaining blocks can complete.
+    ///
+    /// A row with no known springs unfolds into one long unknown stretch, which is
+    /// counted directly instead.
+    fn possibilities_unfolded(&self, factor: usize, separator: Condition) -> BigUint {
+        if factor == 0 {
+            return BigUint::one();
+        }
+        if self.damaged_groups.is_empty() {
+            let fits = !self.springs.contains(&Condition::Damaged)
+                && (factor == 1 || separator != Condition::Damaged);
+            return if fits {
+                BigUint::one()
+            } else {
+                BigUint::zero()
+            };
+        }
+
+        let num_groups = self.damaged_groups.len();
+        let total_groups = factor * num_groups;
+        if self.springs.iter().all(|c| *c == Condition::Unknown)
+            && (factor == 1 || separator == Condition::Unknown)
+        {
+            // Every group but the last needs an operational spring after it, and the
+            // remaining operational springs can go in any of the gaps around them.
+            let num_springs = factor * (self.springs.len() + 1) - 1;
+            let damaged = factor * self.damaged_groups.iter().sum::<usize>();
+            return match (num_springs + 1).checked_sub(damaged) {
+                Some(free) => binomial(free, total_groups),
+                None => BigUint::zero(),
+            };
+        }
+
+        // The unfolded row is `head + (tail + separator + head) * (factor - 1) + tail`,
+        // cut just after the springs' last operational spring if they have one, so
+        // that scans cross from one middle block to the next in the free state.
+        let cut = self
+            .springs
+            .iter()
+            .rposition(|c| *c == Condition::Operational)
+            .map_or(0, |idx| idx + 1);
+        let (head, tail) = self.springs.split_at(cut);
+        let middle = [tail, &[separator], head].concat();
+
+        let max_group = *self.damaged_groups.iter().max().unwrap();
+        let runs = [Run::Free, Run::Closed]
+            .into_iter()
+            .chain((1..max_group).map(Run::Open))
+            .collect::<Vec<Run>>();
+        // Transfers across `cells` for every start state, indexed by
+        // `group * runs.len() + slot`.
+        let transfers = |cells: &[Condition], from_start: bool| {
+            (0..num_groups)
+                .flat_map(|group| runs.iter().map(move |run| (group, *run)))
+                .map(|(group, run)| match run {
+                    _ if from_start && (group, run) != (0, Run::Free) => Vec::new(),
+                    Run::Open(placed) if placed >= self.damaged_groups[group] => Vec::new(),
+                    _ => transfers_from(cells, &self.damaged_groups, group, run)
+                        .into_iter()
+                        .map(|(completed, run, ways)| (completed, run.slot(), ways))
+                        .collect(),
+                })
+                .collect::<Vec<Vec<(usize, usize, usize)>>>()
+        };
+        let (head, middle, tail) = (
+            transfers(head, true),
+            transfers(&middle, false),
+            transfers(tail, false),
+        );
+        // The fewest and most groups a block can complete, whatever state it starts in.
+        // If a block can't be crossed at all nothing gets past it, so any bounds do.
+        let span = |transfers: &[Vec<(usize, usize, usize)>]| {
+            let completed = transfers.iter().flatten().map(|(completed, ..)| *completed);
+            completed
+                .clone()
+                .min()
+                .zip(completed.max())
+                .unwrap_or((0, 0))
+        };
+        let (middle_span, tail_span) = (span(&middle), span(&tail));
+
+        // `states[(completed - lo) * runs.len() + slot]`, over a window of completed
+        // groups starting at `lo`.
+        let mut lo = 0;
+        let mut states = vec![BigUint::zero(); runs.len()];
+        states[Run::Free.slot()] = BigUint::one();
+        for block in 0..=factor {
+            let transfers = match block {
+                0 => &head,
+                _ if block == factor => &tail,
+                _ => &middle,
+            };
+            let (fewest, most) = span(transfers);
+            let (fewest_left, most_left) = if block == factor {
+                (0, 0)
+            } else {
+                let middles_left = (factor - 1).saturating_sub(block);
+                (
+                    middles_left * middle_span.0 + tail_span.0,
+                    middles_left * middle_span.1 + tail_span.1,
+                )
+            };
+            let hi = lo + states.len() / runs.len() - 1;
+            let next_lo = (lo + fewest).max(total_groups.saturating_sub(most_left));
+            let next_hi = match total_groups.checked_sub(fewest_left) {
+                Some(limit) => (hi + most).min(limit),
+                None => return BigUint::zero(),
+            };
+            if next_lo > next_hi {
+                return BigUint::zero();
+            }
+
+            let mut next_states = vec![BigUint::zero(); (next_hi - next_lo + 1) * runs.len()];
+            for (idx, count) in states.iter().enumerate() {
+                if count.is_zero() {
+                    continue;
+                }
+                let completed = lo + idx / runs.len();
+                let key = (completed % num_groups) * runs.len() + idx % runs.len();
+                for (newly_completed, slot, ways) in &transfers[key] {
+                    let completed = completed + newly_completed;
+                    if completed < next_lo || completed > next_hi {
+                        continue;
+                    }
+                    let next = &mut next_states[(completed - next_lo) * runs.len() + slot];
+                    if *ways == 1 {
+                        *next += count;
+                    } else {
+                        *next += count * *ways;
+                    }
+                }
+            }
+            (lo, states) = (next_lo, next_states);
+        }
+
+        // Nothing is left after the tail, so the window is just `total_groups`.
+        &states[Run::Free.slot()] + &states[Run::Closed.slot()]
+    }
+}
+
+/// Each row of the puzzle is one line of a nonogram, so a full grid can be solved
+/// by repeatedly settling every cell that a single row or column forces.
+type Grid = Vec<Vec<Condition>>;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+enum Line {
+    Row(usize),
+    Col(usize),
+}
+
+/// A line whose clue can't be satisfied by the cells already settled.
+#[derive(Debug, Eq, PartialEq)]
+struct Contradiction(Line);
+
+#[derive(Debug, Eq, PartialEq)]
+enum Solution {
+    Unique(Grid),
+    /// Two different grids that both satisfy every clue.
+    Multiple(Grid, Grid),
+}
+
+/// Why a nonogram's clues couldn't be parsed. `line` counts from 1.
+#[derive(Debug, Eq, PartialEq)]
+enum ClueError {
+    /// There is no blank line between the row clues and the column clues.
+    MissingSeparator,
+    /// A line that isn't `0` or a comma-separated list of positive group sizes,
+    /// including any blank line after the separator.
+    BadClue { line: usize, text: String },
+}
+
+struct Nonogram {
+    row_clues: Vec<Vec<usize>>,
+    col_clues: Vec<Vec<usize>>,
+}
+
+impl Nonogram {
+    /// Parses the row clues, a blank line, then the column clues, one line each in
+    /// the puzzle's `1,1,3` format. A line with no groups is written `0`, since the
+    /// only blank line allowed is the one between rows and columns.
+    fn parse_input(input: &str) -> Result<Nonogram, ClueError> {
+        let mut row_clues = Vec::new();
+        let mut col_clues = None;
+        for (idx, line) in input.lines().enumerate() {
+            let bad_clue = || ClueError::BadClue {
+                line: idx + 1,
+                text: String::from(line),
+            };
+            let clues = match (line.trim(), &mut col_clues) {
+                ("", None) => {
+                    col_clues = Some(Vec::new());
+                    continue;
+                }
+                ("", Some(_)) => return Err(bad_clue()),
+                (_, None) => &mut row_clues,
+                (_, Some(col_clues)) => col_clues,
+            };
+            let groups = match line.trim() {
+                "0" => Vec::new(),
+                line => line
+                    .split(',')
+                    .map(|group| match group.trim().parse::<usize>() {
+                        Ok(len) if len > 0 => Ok(len),
+                        _ => Err(bad_clue()),
+                    })
+                    .collect::<Result<Vec<usize>, ClueError>>()?,
+            };
+            clues.push(groups);
+        }
+        Ok(Nonogram {
+            row_clues,
+            col_clues: col_clues.ok_or(ClueError::MissingSeparator)?,
+        })
+    }
+
+    fn line(&self, grid: &Grid, line: Line) -> Row {
+        match line {
+            Line::Row(row_idx) => Row {
+                springs: grid[row_idx].clone(),
+                damaged_groups: self.row_clues[row_idx].clone(),
+            },
+            Line::Col(col_idx) => Row {
+                springs: grid.iter().map(|row| row[col_idx]).collect(),
+                damaged_groups: self.col_clues[col_idx].clone(),
+            },
+        }
+    }
+
+    /// Settles every cell forced by a single line, until no line forces any more.
+    fn propagate(
+        &self,
+        grid: &mut Grid,
+        arrangements: &mut Arrangements,
+    ) -> Result<(), Contradiction> {
+        let mut dirty = (0..self.row_clues.len())
+            .map(Line::Row)
+            .chain((0..self.col_clues.len()).map(Line::Col))
+            .collect::<VecDeque<Line>>();
+        let mut queued = dirty.iter().copied().collect::<HashSet<Line>>();
+        while let Some(line) = dirty.pop_front() {
+            queued.remove(&line);
+            let forced = self
+                .line(grid, line)
+                .forced_cells(arrangements)
+                .ok_or(Contradiction(line))?;
+            for (idx, condition) in forced.into_iter().enumerate() {
+                let (cell, crossing) = match line {
+                    Line::Row(row_idx) => (&mut grid[row_idx][idx], Line::Col(idx)),
+                    Line::Col(col_idx) => (&mut grid[idx][col_idx], Line::Row(idx)),
+                };
+                if *cell != condition {
+                    *cell = condition;
+                    if queued.insert(crossing) {
+                        dirty.push_back(crossing);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Propagates, then guesses the first unsettled cell and recurses, stopping as
+    /// soon as two solutions are found.
+    fn search(
+        &self,
+        mut grid: Grid,
+        arrangements: &mut Arrangements,
+        solutions: &mut Vec<Grid>,
+    ) -> Result<(), Contradiction> {
+        self.propagate(&mut grid, arrangements)?;
+        let unknown = grid.iter().enumerate().find_map(|(row_idx, row)| {
+            row.iter()
+                .position(|c| *c == Condition::Unknown)
+                .map(|col_idx| (row_idx, col_idx))
+        });
+        let Some((row_idx, col_idx)) = unknown else {
+            solutions.push(grid);
+            return Ok(());
+        };
+
+        let mut result = Ok(());
+        for guess in [Condition::Damaged, Condition::Operational] {
+            if solutions.len() >= 2 {
+                break;
+            }
+            let mut guessed = grid.clone();
+            guessed[row_idx][col_idx] = guess;
+            result = self.search(guessed, arrangements, solutions);
+        }
+        if solutions.is_empty() {
+            result
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Solves the grid, or reports the line that proved it unsolvable. If every
+    /// guess fails, the contradiction is the one found on the last guess tried.
+    fn solve(&self) -> Result<Solution, Contradiction> {
+        let grid = vec![vec![Condition::Unknown; self.col_clues.len()]; self.row_clues.len()];
+        let mut solutions = Vec::new();
+        self.search(grid, &mut Arrangements::default(), &mut solutions)?;
+        let mut solutions = solutions.into_iter();
+        match (solutions.next(), solutions.next()) {
+            (Some(first), Some(second)) => Ok(Solution::Multiple(first, second)),
+            (Some(first), None) => Ok(Solution::Unique(first)),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn render(grid: &Grid) -> String {
+    grid.iter()
+        .map(|row| row.iter().map(|c| c.to_char()).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+impl Arrangements {
+    /// For each spring, the number of arrangements of `row` in which it's damaged.
+    ///
+    /// A forward pass counts the ways `before[i][j]` to reach spring `i` with the first
+    /// `j` groups placed, mirroring `ways` from the other end. Placing group `j` at
+    /// spring `i` then happens in `before[i][j] * ways[after][j + 1]` arrangements,
+    /// and every spring the group covers is damaged in all of them.
+    fn damaged_counts(&mut self, row: &Row) -> &[usize] {
+        let (springs, damaged_groups) = (&row.springs, &row.damaged_groups);
+        self.fill(springs, damaged_groups);
+        let (num_springs, num_groups) = (springs.len(), damaged_groups.len());
+        self.before.clear();
+        self.before.resize((num_springs + 1) * (num_groups + 1), 0);
+        self.before[0] = 1;
+        self.group_starts.clear();
+        self.group_starts.resize(num_springs, 0);
+        self.group_ends.clear();
+        self.group_ends.resize(num_springs + 1, 0);
+        for spring_idx in 0..num_springs {
+            for group_idx in 0..=num_groups {
+                let before = self.before[spring_idx * (num_groups + 1) + group_idx];
+                if before == 0 {
+                    continue;
+                }
+                if springs[spring_idx] != Condition::Damaged {
+                    self.before[(spring_idx + 1) * (num_groups + 1) + group_idx] += before;
+                }
+                if let Some(after_group) =
+                    self.place_group(springs, damaged_groups, spring_idx, group_idx)
+                {
+                    self.before[after_group * (num_groups + 1) + group_idx + 1] += before;
+                    // Never more than the total count, so this only overflows if that does.
+                    let through = before
+                        .checked_mul(self.ways(after_group, group_idx + 1))
+                        .expect("arrangement count overflows usize");
+                    self.group_starts[spring_idx] += through;
+                    self.group_ends[spring_idx + damaged_groups[group_idx]] += through;
+                }
+            }
+        }
+
+        self.damaged.clear();
+        // Taking away the groups that ended before adding the ones that start keeps
+        // `running` an actual count throughout, so it can't wrap.
+        let mut running = 0;
+        for spring_idx in 0..num_springs {
+            running -= self.group_ends[spring_idx];
+            running += self.group_starts[spring_idx];
+            self.damaged.push(running);
+        }
+        &self.damaged
+    }
+}
+
+impl Row {
+    /// The row with every cell that has the same condition in all arrangements
+    /// settled, or `None` if there are no arrangements at all.
+    fn forced_cells(&self, arrangements: &mut Arrangements) -> Option<Vec<Condition>> {
+        let total = arrangements.count(self);
+        if total == 0 {
+            return None;
+        }
+        let springs = arrangements
+            .damaged_counts(self)
+            .iter()
+            .map(|damaged| match *damaged {
+                0 => Condition::Operational,
+                damaged if damaged == total => Condition::Damaged,
+                _ => Condition::Unknown,
+            })
+            .collect();
+        Some(springs)
+    }
+
+    /// The fraction of arrangements in which each spring is damaged, or `None` if
+    /// there are no arrangements at all.
+    fn damaged_probabilities(&self) -> Option<Vec<f64>> {
+        let mut arrangements = Arrangements::default();
+        let total = arrangements.count(self);
+        if total == 0 {
+            return None;
+        }
+        let probabilities = arrangements
+            .damaged_counts(self)
+            .iter()
+            .map(|damaged| *damaged as f64 / total as f64)
+            .collect();
+        Some(probabilities)
+    }
+}
+
 fn part_1(input: &str) -> usize {
+    let mut arrangements = Arrangements::default();
     input
         .lines()
         .map(Row::parse_line)
-        .map(|r| r.possibilities_r(&mut HashMap::new()))
+        .map(|r| arrangements.count(&r))
         .sum()
 }
 
 fn part_2(input: &str) -> usize {
+    let mut arrangements = Arrangements::default();
     input
         .lines()
         .map(Row::parse_line_part_2)
-        .map(|r| r.possibilities_r(&mut HashMap::new()))
+        .map(|r| arrangements.count(&r))
         .sum()
 }
 
@@ -138,6 +790,10 @@ fn part_2(input: &str) -> usize {
 mod tests {
     use crate::aoc_2023::data::day_12::*;
     use crate::aoc_2023::day_12::*;
+    use crate::aoc_2023::Rng;
+    use num::{BigUint, One};
+    use std::collections::{HashMap, HashSet};
+    use std::time::{Duration, Instant};
 
     #[test]
     fn part_1_sample() {
@@ -162,4 +818,414 @@ mod tests {
         let result = part_2(INPUT);
         assert_eq!(result, 8475948826693);
     }
+
+    fn random_row(rng: &mut Rng, num_springs: usize) -> Row {
+        let springs = (0..num_springs)
+            .map(|_| match rng.below(3) {
+                0 => Condition::Operational,
+                1 => Condition::Damaged,
+                _ => Condition::Unknown,
+            })
+            .collect();
+        let num_groups = rng.below(5);
+        let damaged_groups = (0..num_groups).map(|_| 1 + rng.below(4)).collect();
+        Row {
+            springs,
+            damaged_groups,
+        }
+    }
+
+    #[test]
+    fn arrangements_match_recursive_sample() {
+        let mut arrangements = Arrangements::default();
+        for line in SAMPLE.lines() {
+            for row in [Row::parse_line(line), Row::parse_line_part_2(line)] {
+                assert_eq!(
+                    arrangements.count(&row),
+                    row.possibilities_r(&mut HashMap::new())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn arrangements_match_recursive_random() {
+        let mut rng = Rng::new(0x2023_1212);
+        let mut arrangements = Arrangements::default();
+        for num_springs in 0..200 {
+            let row = random_row(&mut rng, num_springs % 20);
+            assert_eq!(
+                arrangements.count(&row),
+                row.possibilities_r(&mut HashMap::new())
+            );
+        }
+    }
+
+    /// Every way of filling in the unknown springs that matches the groups.
+    fn brute_force_arrangements(row: &Row) -> Vec<String> {
+        let unknowns = row
+            .springs
+            .iter()
+            .filter(|c| **c == Condition::Unknown)
+            .count();
+        let mut arrangements = (0..1usize << unknowns)
+            .map(|mask| {
+                let mut bit = 0;
+                row.springs
+                    .iter()
+                    .map(|c| match c {
+                        Condition::Damaged => '#',
+                        Condition::Operational => '.',
+                        Condition::Unknown => {
+                            bit += 1;
+                            if mask & (1 << (bit - 1)) != 0 {
+                                '#'
+                            } else {
+                                '.'
+                            }
+                        }
+                    })
+                    .collect::<String>()
+            })
+            .filter(|arrangement| {
+                arrangement
+                    .split('.')
+                    .filter(|group| !group.is_empty())
+                    .map(|group| group.len())
+                    .eq(row.damaged_groups.iter().copied())
+            })
+            .collect::<Vec<String>>();
+        arrangements.sort();
+        arrangements
+    }
+
+    #[test]
+    fn arrangements_sample() {
+        let row = Row::parse_line("?###???????? 3,2,1");
+        let arrangements = row.arrangements().collect::<Vec<String>>();
+        assert_eq!(arrangements.len(), 10);
+        assert_eq!(arrangements[0], ".###.##.#...");
+        assert_eq!(arrangements[9], ".###....##.#");
+
+        for line in SAMPLE.lines() {
+            let row = Row::parse_line(line);
+            assert_eq!(
+                row.arrangements().collect::<Vec<String>>(),
+                brute_force_arrangements(&row)
+            );
+        }
+    }
+
+    #[test]
+    fn arrangements_random() {
+        let mut rng = Rng::new(0x2023_1231);
+        for num_springs in 0..200 {
+            let row = random_row(&mut rng, num_springs % 14);
+            assert_eq!(
+                row.arrangements().collect::<Vec<String>>(),
+                brute_force_arrangements(&row)
+            );
+        }
+    }
+
+    #[test]
+    fn arrangements_are_lazy() {
+        let row = Row::parse_line_part_2("?###???????? 3,2,1");
+        let first = row.arrangements().take(3).collect::<Vec<String>>();
+        assert_eq!(first.len(), 3);
+        assert!(first.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn random_arrangement_covers_all() {
+        let row = Row::parse_line("?###???????? 3,2,1");
+        let mut rng = Rng::new(0x2023_1213);
+        let mut seen = HashMap::new();
+        for _ in 0..1000 {
+            let arrangement = row.random_arrangement(|n| rng.below(n)).unwrap();
+            *seen.entry(arrangement).or_insert(0) += 1;
+        }
+        assert_eq!(
+            seen.keys().cloned().collect::<HashSet<String>>(),
+            row.arrangements().collect::<HashSet<String>>()
+        );
+        assert!(seen.values().all(|n| *n > 50));
+
+        let impossible = Row::parse_line("#.# 3");
+        assert_eq!(impossible.random_arrangement(|_| 0), None);
+    }
+
+    #[test]
+    fn unfold_matches_parse_line_part_2() {
+        let row = Row::parse_line(".# 1");
+        let unfolded = row.unfold(5, Condition::Unknown);
+        assert_eq!(unfolded.springs.len(), 14);
+        assert_eq!(unfolded.damaged_groups, vec![1; 5]);
+        for line in SAMPLE.lines() {
+            assert!(
+                Row::parse_line_part_2(line)
+                    == Row::parse_line_unfolded(line, 5, Condition::Unknown)
+            );
+        }
+    }
+
+    #[test]
+    fn possibilities_unfolded_sample() {
+        let counts = SAMPLE
+            .lines()
+            .map(|line| Row::parse_line(line).possibilities_unfolded(5, Condition::Unknown))
+            .collect::<Vec<BigUint>>();
+        assert_eq!(
+            counts,
+            [1u32, 16384, 1, 16, 2500, 506250]
+                .map(BigUint::from)
+                .to_vec()
+        );
+    }
+
+    #[test]
+    fn possibilities_unfolded_matches_materialized() {
+        let mut rng = Rng::new(0x2023_1232);
+        let mut arrangements = Arrangements::default();
+        let rows = SAMPLE
+            .lines()
+            .map(Row::parse_line)
+            .chain((0..150).map(|idx| random_row(&mut rng, idx % 9)))
+            .chain(["? 1", "??? 1,1", "?????? 2,1", "???????? 1,3,1"].map(Row::parse_line))
+            .collect::<Vec<Row>>();
+        for row in rows {
+            for separator in [
+                Condition::Unknown,
+                Condition::Operational,
+                Condition::Damaged,
+            ] {
+                for factor in 0..5 {
+                    assert_eq!(
+                        row.possibilities_unfolded(factor, separator),
+                        BigUint::from(arrangements.count(&row.unfold(factor, separator))),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn possibilities_unfolded_large_factor() {
+        let row = Row::parse_line("???.### 1,1,3");
+        assert_eq!(
+            row.possibilities_unfolded(1000, Condition::Unknown),
+            BigUint::one()
+        );
+        let row = Row::parse_line("????.#...#... 4,1,1");
+        assert_eq!(
+            row.possibilities_unfolded(1000, Condition::Unknown),
+            BigUint::from(2u32).pow(999)
+        );
+        for line in SAMPLE.lines() {
+            let count = Row::parse_line(line).possibilities_unfolded(1000, Condition::Unknown);
+            assert!(count >= BigUint::one());
+        }
+    }
+
+    #[test]
+    fn possibilities_unfolded_unknown_rows() {
+        for n in 0..40 {
+            for k in 0..45 {
+                assert_eq!(
+                    binomial(n, k),
+                    num::integer::binomial(BigUint::from(n), BigUint::from(k))
+                );
+            }
+        }
+
+        // A thousand copies of 18 unknown springs and their separators have 18999
+        // springs. With 4000 single damaged springs that leaves 15000 operational
+        // springs, and the groups go in the gaps before each of them or at the end.
+        let start = Instant::now();
+        let row = Row::parse_line("?????????????????? 1,1,1,1");
+        assert_eq!(
+            row.possibilities_unfolded(1000, Condition::Unknown),
+            binomial(15000, 4000)
+        );
+        let row = Row::parse_line("???????????????????? 1,1,1,1,1,1");
+        assert_eq!(
+            row.possibilities_unfolded(1000, Condition::Unknown),
+            binomial(15000, 6000)
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    fn clues(lines: &[Vec<Condition>]) -> Vec<Vec<usize>> {
+        lines
+            .iter()
+            .map(|line| {
+                line.split(|c| *c == Condition::Operational)
+                    .filter(|group| !group.is_empty())
+                    .map(|group| group.len())
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn satisfies(nonogram: &Nonogram, grid: &Grid) -> bool {
+        let cols = (0..grid[0].len())
+            .map(|col_idx| grid.iter().map(|row| row[col_idx]).collect())
+            .collect::<Grid>();
+        clues(grid) == nonogram.row_clues && clues(&cols) == nonogram.col_clues
+    }
+
+    #[test]
+    fn nonogram_unique() {
+        let nonogram = Nonogram::parse_input("1\n3\n1,1\n\n2\n2\n2").unwrap();
+        let Ok(Solution::Unique(grid)) = nonogram.solve() else {
+            panic!("expected a unique solution");
+        };
+        assert_eq!(render(&grid), ".#.\n###\n#.#");
+    }
+
+    #[test]
+    fn nonogram_multiple() {
+        let nonogram = Nonogram::parse_input("1\n1\n\n1\n1").unwrap();
+        let Ok(Solution::Multiple(first, second)) = nonogram.solve() else {
+            panic!("expected multiple solutions");
+        };
+        assert_ne!(first, second);
+        assert!(satisfies(&nonogram, &first));
+        assert!(satisfies(&nonogram, &second));
+    }
+
+    #[test]
+    fn nonogram_contradiction() {
+        let nonogram = Nonogram::parse_input("3\n0\n\n1\n1").unwrap();
+        assert_eq!(nonogram.solve(), Err(Contradiction(Line::Row(0))));
+
+        let nonogram = Nonogram::parse_input("1\n1\n\n2\n0").unwrap();
+        assert!(nonogram.solve().is_ok());
+        let nonogram = Nonogram::parse_input("1\n1\n\n2\n1").unwrap();
+        assert!(nonogram.solve().is_err());
+    }
+
+    #[test]
+    fn nonogram_random_round_trip() {
+        let mut rng = Rng::new(0x2023_1233);
+        for size in 1..9 {
+            let grid = (0..size)
+                .map(|_| {
+                    (0..size + 1)
+                        .map(|_| match rng.below(2) {
+                            0 => Condition::Damaged,
+                            _ => Condition::Operational,
+                        })
+                        .collect()
+                })
+                .collect::<Grid>();
+            let cols = (0..size + 1)
+                .map(|col_idx| grid.iter().map(|row| row[col_idx]).collect())
+                .collect::<Grid>();
+            let nonogram = Nonogram {
+                row_clues: clues(&grid),
+                col_clues: clues(&cols),
+            };
+            match nonogram.solve() {
+                Ok(Solution::Unique(solved)) => assert_eq!(solved, grid),
+                Ok(Solution::Multiple(first, second)) => {
+                    assert!(satisfies(&nonogram, &first));
+                    assert!(satisfies(&nonogram, &second));
+                    assert_ne!(first, second);
+                }
+                Err(contradiction) => panic!("{:?}", contradiction),
+            }
+        }
+    }
+
+    #[test]
+    fn forced_cells_sample() {
+        let mut arrangements = Arrangements::default();
+        let row = Row::parse_line("?###???????? 3,2,1");
+        let forced = row.forced_cells(&mut arrangements).unwrap();
+        assert_eq!(
+            forced.iter().map(|c| c.to_char()).collect::<String>(),
+            ".###.???????"
+        );
+
+        let row = Row::parse_line("???.### 1,1,3");
+        let forced = row.forced_cells(&mut arrangements).unwrap();
+        assert_eq!(
+            forced.iter().map(|c| c.to_char()).collect::<String>(),
+            "#.#.###"
+        );
+
+        let row = Row::parse_line("#.# 3");
+        assert_eq!(row.forced_cells(&mut arrangements), None);
+    }
+
+    #[test]
+    fn damaged_counts_match_brute_force() {
+        let mut rng = Rng::new(0x2023_1234);
+        let mut arrangements = Arrangements::default();
+        let rows = SAMPLE
+            .lines()
+            .map(Row::parse_line)
+            .chain((0..200).map(|idx| random_row(&mut rng, idx % 14)))
+            .collect::<Vec<Row>>();
+        for row in rows {
+            let all = brute_force_arrangements(&row);
+            let expected = (0..row.springs.len())
+                .map(|idx| {
+                    all.iter()
+                        .filter(|arrangement| arrangement.as_bytes()[idx] == b'#')
+                        .count()
+                })
+                .collect::<Vec<usize>>();
+            assert_eq!(arrangements.damaged_counts(&row), expected);
+        }
+    }
+
+    #[test]
+    fn damaged_probabilities_sample() {
+        let row = Row::parse_line(".??..??...?##. 1,1,3");
+        let probabilities = row.damaged_probabilities().unwrap();
+        assert_eq!(probabilities[0], 0.0);
+        assert_eq!(probabilities[1], 0.5);
+        assert_eq!(probabilities[5], 0.5);
+        assert_eq!(probabilities[9], 0.0);
+        assert_eq!(probabilities[10], 1.0);
+        let expected_damaged = probabilities.iter().sum::<f64>();
+        assert!((expected_damaged - 5.0).abs() < 1e-9);
+
+        assert_eq!(Row::parse_line("#.# 3").damaged_probabilities(), None);
+    }
+
+    #[test]
+    fn nonogram_parse_errors() {
+        let nonogram = Nonogram::parse_input("1\n0\n\n1\n0").unwrap();
+        assert_eq!(nonogram.row_clues, vec![vec![1], vec![]]);
+        assert_eq!(nonogram.col_clues, vec![vec![1], vec![]]);
+
+        assert_eq!(
+            Nonogram::parse_input("1\n1").err(),
+            Some(ClueError::MissingSeparator)
+        );
+        assert_eq!(
+            Nonogram::parse_input("1\n\n\n1\n1").err(),
+            Some(ClueError::BadClue {
+                line: 3,
+                text: String::new()
+            })
+        );
+        for (input, line, text) in [
+            ("1,x\n\n1", 1, "1,x"),
+            ("1\n\n1,,2", 3, "1,,2"),
+            ("1\n\n0,1", 3, "0,1"),
+            ("-1\n\n1", 1, "-1"),
+        ] {
+            assert_eq!(
+                Nonogram::parse_input(input).err(),
+                Some(ClueError::BadClue {
+                    line,
+                    text: String::from(text)
+                })
+            );
+        }
+    }
 }
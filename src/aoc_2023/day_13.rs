@@ -18,58 +18,315 @@ impl Terrain {
         Terrain { rows, cols }
     }
 
-    fn find_reflection(&self) -> Reflection {
-        find_1d_reflection(&self.rows).map_or_else(
-            || {
-                find_1d_reflection(&self.cols)
-                    .map(|idx| Reflection::Col(idx))
-                    .unwrap()
-            },
-            |idx| Reflection::Row(idx),
-        )
-    }
-}
-
-fn find_1d_reflection(dim: &Vec<String>) -> Option<usize> {
-    (1..dim.len())
-        .filter_map(|idx| {
-            if dim[idx - 1] == dim[idx] {
-                Some(idx - 1)
-            } else {
-                None
+    fn find_reflection(&self) -> Option<Reflection> {
+        self.find_reflection_with_smudges(Smudges::Exactly(0))
+            .map(|m| m.reflection)
+    }
+
+    fn find_reflection_with_smudge(&self) -> Option<Reflection> {
+        self.find_reflection_with_smudges(Smudges::Exactly(1))
+            .map(|m| m.reflection)
+    }
+
+    /// Every row reflection followed by every column reflection allowed by
+    /// `smudges`, which is empty if the pattern has none.
+    fn reflections(&self, smudges: Smudges) -> Vec<Axis> {
+        reflections(&self.rows, &self.cols, smudges)
+    }
+
+    /// The first row reflection, or else the first column reflection, whose two
+    /// halves differ in a number of cells allowed by `smudges`.
+    fn find_reflection_with_smudges(&self, smudges: Smudges) -> Option<Mirror> {
+        find_reflection(&self.rows, &self.cols, smudges)
+    }
+
+    fn pack(&self) -> PackedTerrain {
+        PackedTerrain {
+            rows: self.rows.iter().map(|row| Bits::from_line(row)).collect(),
+            cols: self.cols.iter().map(|col| Bits::from_line(col)).collect(),
+        }
+    }
+}
+
+/// Symmetries of the whole pattern other than mirror lines. The quarter turn and
+/// both diagonals only exist for square patterns.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Symmetry {
+    HalfTurn,
+    QuarterTurn,
+    MainDiagonal,
+    AntiDiagonal,
+}
+
+impl Terrain {
+    fn cell(&self, row_idx: usize, col_idx: usize) -> u8 {
+        self.rows[row_idx].as_bytes()[col_idx]
+    }
+
+    /// The fewest cells that have to flip for the pattern to have `symmetry`, or
+    /// `None` if the pattern's shape can't have it.
+    ///
+    /// Each symmetry other than the quarter turn pairs every row with a row or
+    /// column read in some direction, so mismatches are counted from both ends and
+    /// halved. A quarter turn cycles cells in fours, and each cycle needs its
+    /// minority cells flipped.
+    fn symmetry_smudges(&self, symmetry: Symmetry) -> Option<usize> {
+        let (num_rows, num_cols) = (self.rows.len(), self.cols.len());
+        if symmetry != Symmetry::HalfTurn && num_rows != num_cols {
+            return None;
+        }
+        let mismatches = |image: &dyn Fn(usize) -> Vec<u8>| {
+            (0..num_rows)
+                .map(|row_idx| {
+                    zip(self.rows[row_idx].bytes(), image(row_idx))
+                        .filter(|(l, r)| *l != *r)
+                        .count()
+                })
+                .sum::<usize>()
+                / 2
+        };
+        let reversed = |line: &String| line.bytes().rev().collect::<Vec<u8>>();
+        let smudges = match symmetry {
+            Symmetry::HalfTurn => {
+                mismatches(&|row_idx| reversed(&self.rows[num_rows - 1 - row_idx]))
+            }
+            Symmetry::MainDiagonal => mismatches(&|row_idx| self.cols[row_idx].bytes().collect()),
+            Symmetry::AntiDiagonal => {
+                mismatches(&|row_idx| reversed(&self.cols[num_rows - 1 - row_idx]))
             }
+            Symmetry::QuarterTurn => {
+                let n = num_rows;
+                let mut smudges = 0;
+                for row_idx in 0..n / 2 {
+                    for col_idx in 0..n.div_ceil(2) {
+                        let orbit = [
+                            self.cell(row_idx, col_idx),
+                            self.cell(col_idx, n - 1 - row_idx),
+                            self.cell(n - 1 - row_idx, n - 1 - col_idx),
+                            self.cell(n - 1 - col_idx, row_idx),
+                        ];
+                        let damaged = orbit.iter().filter(|c| **c == b'#').count();
+                        smudges += damaged.min(4 - damaged);
+                    }
+                }
+                smudges
+            }
+        };
+        Some(smudges)
+    }
+
+    /// Every symmetry the pattern has within `smudges`, with its smudge count.
+    fn symmetries(&self, smudges: Smudges) -> Vec<(Symmetry, usize)> {
+        [
+            Symmetry::HalfTurn,
+            Symmetry::QuarterTurn,
+            Symmetry::MainDiagonal,
+            Symmetry::AntiDiagonal,
+        ]
+        .into_iter()
+        .filter_map(|symmetry| {
+            self.symmetry_smudges(symmetry)
+                .filter(|total| smudges.allows(*total))
+                .map(|total| (symmetry, total))
         })
-        .find_map(|idx| {
-            if is_reflection_line(idx, dim) {
-                Some(idx)
-            } else {
-                None
+        .collect()
+    }
+}
+
+/// A row or column of a pattern, compared against the line it mirrors.
+trait Line {
+    fn distance(&self, other: &Self) -> usize;
+
+    /// Positions along the line where the two lines differ.
+    fn differences(&self, other: &Self) -> Vec<usize>;
+}
+
+impl Line for String {
+    fn distance(&self, other: &Self) -> usize {
+        hamming_distance(self, other)
+    }
+
+    fn differences(&self, other: &Self) -> Vec<usize> {
+        zip(self.chars(), other.chars())
+            .enumerate()
+            .filter(|(_, (l, r))| l != r)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+/// A line packed into 64-bit words with a bit set for every `#`, so comparing
+/// two lines is a word compare and their distance a `count_ones` per word.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn from_line(line: &str) -> Bits {
+        let mut words = vec![0; line.len().div_ceil(64)];
+        for (idx, c) in line.chars().enumerate() {
+            if c == '#' {
+                words[idx / 64] |= 1 << (idx % 64);
+            }
+        }
+        Bits(words)
+    }
+}
+
+impl Line for Bits {
+    fn distance(&self, other: &Self) -> usize {
+        zip(&self.0, &other.0)
+            .map(|(l, r)| (l ^ r).count_ones() as usize)
+            .sum()
+    }
+
+    fn differences(&self, other: &Self) -> Vec<usize> {
+        let mut differences = Vec::new();
+        for (word_idx, (l, r)) in zip(&self.0, &other.0).enumerate() {
+            let mut diff = l ^ r;
+            while diff != 0 {
+                differences.push(word_idx * 64 + diff.trailing_zeros() as usize);
+                diff &= diff - 1;
             }
+        }
+        differences
+    }
+}
+
+/// `Terrain` with every row and column packed into `Bits`.
+struct PackedTerrain {
+    rows: Vec<Bits>,
+    cols: Vec<Bits>,
+}
+
+impl PackedTerrain {
+    fn parse_input(input: &str) -> PackedTerrain {
+        Terrain::parse_input(input).pack()
+    }
+
+    fn reflections(&self, smudges: Smudges) -> Vec<Axis> {
+        reflections(&self.rows, &self.cols, smudges)
+    }
+
+    fn find_reflection_with_smudges(&self, smudges: Smudges) -> Option<Mirror> {
+        find_reflection(&self.rows, &self.cols, smudges)
+    }
+}
+
+fn reflections<L: Line>(rows: &[L], cols: &[L], smudges: Smudges) -> Vec<Axis> {
+    let axes = |dim: &[L], reflection: fn(usize) -> Reflection| {
+        (0..dim.len().saturating_sub(1))
+            .filter_map(|idx| {
+                reflection_distance(idx, dim, smudges.max())
+                    .filter(|total| smudges.allows(*total))
+                    .map(|total| Axis {
+                        reflection: reflection(idx),
+                        extent: (idx + 1).min(dim.len() - idx - 1),
+                        smudges: total,
+                    })
+            })
+            .collect::<Vec<Axis>>()
+    };
+    [axes(rows, Reflection::Row), axes(cols, Reflection::Col)].concat()
+}
+
+fn find_reflection<L: Line>(rows: &[L], cols: &[L], smudges: Smudges) -> Option<Mirror> {
+    find_1d_reflection(rows, smudges)
+        .map(|idx| mirror(rows, Reflection::Row(idx)))
+        .or_else(|| find_1d_reflection(cols, smudges).map(|idx| mirror(cols, Reflection::Col(idx))))
+}
+
+/// The reflection together with every pair of mirrored cells that differ, where
+/// `dim` is the rows for a row reflection and the columns for a column one.
+fn mirror<L: Line>(dim: &[L], reflection: Reflection) -> Mirror {
+    let idx = match reflection {
+        Reflection::Row(idx) | Reflection::Col(idx) => idx,
+    };
+    let smudges = zip((0..=idx).rev(), idx + 1..dim.len())
+        .flat_map(|(left, right)| {
+            dim[left]
+                .differences(&dim[right])
+                .into_iter()
+                .map(move |other_idx| match reflection {
+                    Reflection::Row(_) => ((left, other_idx), (right, other_idx)),
+                    Reflection::Col(_) => ((other_idx, left), (other_idx, right)),
+                })
         })
+        .collect();
+    Mirror {
+        reflection,
+        smudges,
+    }
+}
+
+/// How many cells may differ between the two halves of a reflection.
+#[derive(Copy, Clone)]
+enum Smudges {
+    Exactly(usize),
+    AtMost(usize),
 }
 
-fn is_reflection_line(idx: usize, dim: &Vec<String>) -> bool {
-    let mut iter_left = (0..=idx).rev();
-    let mut iter_right = idx + 1..dim.len();
+impl Smudges {
+    fn max(&self) -> usize {
+        match self {
+            Smudges::Exactly(k) | Smudges::AtMost(k) => *k,
+        }
+    }
 
-    while let (Some(left), Some(right)) = (iter_left.next(), iter_right.next()) {
-        if dim[left] != dim[right] {
-            return false;
+    fn allows(&self, total: usize) -> bool {
+        match self {
+            Smudges::Exactly(k) => total == *k,
+            Smudges::AtMost(k) => total <= *k,
         }
     }
-    return true;
 }
 
+fn find_1d_reflection<L: Line>(dim: &[L], smudges: Smudges) -> Option<usize> {
+    (0..dim.len().saturating_sub(1)).find(|idx| {
+        reflection_distance(*idx, dim, smudges.max()).is_some_and(|total| smudges.allows(total))
+    })
+}
+
+/// Total Hamming distance between the lines on either side of a reflection after
+/// `idx`, or `None` once it exceeds `max`.
+fn reflection_distance<L: Line>(idx: usize, dim: &[L], max: usize) -> Option<usize> {
+    let mut total = 0;
+    for (left, right) in zip((0..=idx).rev(), idx + 1..dim.len()) {
+        total += dim[left].distance(&dim[right]);
+        if total > max {
+            return None;
+        }
+    }
+    Some(total)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 enum Reflection {
     Row(usize),
     Col(usize),
 }
 
+/// A reflection and the cells that would have to flip for it to be exact, as
+/// (row, col) pairs of each differing cell and its mirror image.
+#[derive(Debug, Eq, PartialEq)]
+struct Mirror {
+    reflection: Reflection,
+    smudges: Vec<((usize, usize), (usize, usize))>,
+}
+
+/// One mirror line, with how many lines on each side it reflects before reaching
+/// the edge of the pattern and how many cells differ across it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Axis {
+    reflection: Reflection,
+    extent: usize,
+    smudges: usize,
+}
+
 fn part_1(input: &str) -> usize {
     input
         .split("\n\n")
         .map(Terrain::parse_input)
-        .map(|t| t.find_reflection())
+        .map(|t| t.find_reflection().unwrap())
         .map(|r| match r {
             Reflection::Row(idx) => 100 * (idx + 1),
             Reflection::Col(idx) => idx + 1,
@@ -83,53 +340,11 @@ fn hamming_distance(left: &str, right: &str) -> usize {
         .sum()
 }
 
-fn is_reflection_line_with_smudge(idx: usize, dim: &Vec<String>) -> bool {
-    let mut iter_left = (0..=idx).rev();
-    let mut iter_right = idx + 1..dim.len();
-    let mut total_hamming_distance = 0;
-
-    while let (Some(left), Some(right)) = (iter_left.next(), iter_right.next()) {
-        match hamming_distance(&dim[left], &dim[right]) {
-            d @ 0 | d @ 1 => total_hamming_distance += d,
-            _ => return false,
-        }
-    }
-    return total_hamming_distance == 1;
-}
-
-fn find_reflection_1d_with_smudge(dim: &Vec<String>) -> Option<usize> {
-    (1..dim.len())
-        .filter_map(|idx| match hamming_distance(&dim[idx - 1], &dim[idx]) {
-            0 | 1 => Some(idx - 1),
-            _ => None,
-        })
-        .find_map(|idx| {
-            if is_reflection_line_with_smudge(idx, dim) {
-                Some(idx)
-            } else {
-                None
-            }
-        })
-}
-
-impl Terrain {
-    fn find_reflection_with_smudge(&self) -> Reflection {
-        find_reflection_1d_with_smudge(&self.rows).map_or_else(
-            || {
-                find_reflection_1d_with_smudge(&self.cols)
-                    .map(|idx| Reflection::Col(idx))
-                    .unwrap()
-            },
-            |idx| Reflection::Row(idx),
-        )
-    }
-}
-
 fn part_2(input: &str) -> usize {
     input
         .split("\n\n")
         .map(Terrain::parse_input)
-        .map(|t| t.find_reflection_with_smudge())
+        .map(|t| t.find_reflection_with_smudge().unwrap())
         .map(|r| match r {
             Reflection::Row(idx) => 100 * (idx + 1),
             Reflection::Col(idx) => idx + 1,
@@ -141,6 +356,7 @@ fn part_2(input: &str) -> usize {
 mod tests {
     use crate::aoc_2023::data::day_13::*;
     use crate::aoc_2023::day_13::*;
+    use crate::aoc_2023::Rng;
 
     #[test]
     fn part_1_sample() {
@@ -165,4 +381,225 @@ mod tests {
         let result = part_2(INPUT);
         assert_eq!(result, 35554);
     }
+
+    #[test]
+    fn find_reflection_with_smudges_sample() {
+        let terrains = SAMPLE
+            .split("\n\n")
+            .map(Terrain::parse_input)
+            .collect::<Vec<Terrain>>();
+
+        let mirror = terrains[0]
+            .find_reflection_with_smudges(Smudges::Exactly(1))
+            .unwrap();
+        assert_eq!(mirror.reflection, Reflection::Row(2));
+        assert_eq!(mirror.smudges, vec![((0, 0), (5, 0))]);
+
+        let mirror = terrains[1]
+            .find_reflection_with_smudges(Smudges::Exactly(1))
+            .unwrap();
+        assert_eq!(mirror.reflection, Reflection::Row(0));
+        assert_eq!(mirror.smudges, vec![((0, 4), (1, 4))]);
+
+        let mirror = terrains[0]
+            .find_reflection_with_smudges(Smudges::AtMost(1))
+            .unwrap();
+        assert_eq!(mirror.reflection, Reflection::Row(2));
+        let mirror = terrains[1]
+            .find_reflection_with_smudges(Smudges::AtMost(0))
+            .unwrap();
+        assert_eq!(mirror.reflection, Reflection::Row(3));
+        assert!(mirror.smudges.is_empty());
+    }
+
+    #[test]
+    fn find_reflection_with_smudges_k() {
+        let terrain = Terrain::parse_input("##..\n#..#\n#..#");
+        assert_eq!(
+            terrain
+                .find_reflection_with_smudges(Smudges::Exactly(0))
+                .map(|m| m.reflection),
+            Some(Reflection::Row(1))
+        );
+        let mirror = terrain
+            .find_reflection_with_smudges(Smudges::Exactly(2))
+            .unwrap();
+        assert_eq!(mirror.reflection, Reflection::Row(0));
+        assert_eq!(mirror.smudges, vec![((0, 1), (1, 1)), ((0, 3), (1, 3))]);
+        assert_eq!(
+            terrain.find_reflection_with_smudges(Smudges::Exactly(4)),
+            None
+        );
+    }
+
+    #[test]
+    fn reflections_sample() {
+        let terrains = SAMPLE
+            .split("\n\n")
+            .map(Terrain::parse_input)
+            .collect::<Vec<Terrain>>();
+        assert_eq!(
+            terrains[0].reflections(Smudges::Exactly(0)),
+            vec![Axis {
+                reflection: Reflection::Col(4),
+                extent: 4,
+                smudges: 0,
+            }]
+        );
+        assert_eq!(
+            terrains[1].reflections(Smudges::AtMost(1)),
+            vec![
+                Axis {
+                    reflection: Reflection::Row(0),
+                    extent: 1,
+                    smudges: 1,
+                },
+                Axis {
+                    reflection: Reflection::Row(3),
+                    extent: 3,
+                    smudges: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reflections_many_and_none() {
+        let terrain = Terrain::parse_input("....\n....");
+        let axes = terrain.reflections(Smudges::Exactly(0));
+        assert_eq!(axes.len(), 4);
+        assert_eq!(
+            axes.iter().map(|a| a.extent).collect::<Vec<usize>>(),
+            vec![1, 1, 2, 1]
+        );
+
+        let terrain = Terrain::parse_input("#.\n..");
+        assert!(terrain.reflections(Smudges::Exactly(0)).is_empty());
+        assert_eq!(terrain.find_reflection(), None);
+        assert_eq!(
+            terrain.find_reflection_with_smudge(),
+            Some(Reflection::Row(0))
+        );
+    }
+
+    /// Every other row is a copy of a neighbour with a couple of cells flipped, so
+    /// reflections and smudges turn up often.
+    fn random_pattern(rng: &mut Rng, num_rows: usize, num_cols: usize) -> String {
+        let mut rows: Vec<Vec<u8>> = Vec::new();
+        for row_idx in 0..num_rows {
+            let row = if row_idx % 2 == 1 {
+                let mut row = rows[row_idx - 1].clone();
+                let num_flips = rng.below(3);
+                for _ in 0..num_flips {
+                    let col_idx = rng.below(num_cols);
+                    row[col_idx] = if row[col_idx] == b'#' { b'.' } else { b'#' };
+                }
+                row
+            } else {
+                (0..num_cols)
+                    .map(|_| if rng.below(2) == 0 { b'#' } else { b'.' })
+                    .collect()
+            };
+            rows.push(row);
+        }
+        rows.into_iter()
+            .map(|row| String::from_utf8(row).unwrap())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn bits_distance() {
+        let left = Bits::from_line(&"#.".repeat(50));
+        let right = Bits::from_line(&".#".repeat(50));
+        assert_eq!(left.0.len(), 2);
+        assert_eq!(left.distance(&right), 100);
+        assert_eq!(left.differences(&right), (0..100).collect::<Vec<usize>>());
+        assert_eq!(left, Bits::from_line(&"#.".repeat(50)));
+    }
+
+    #[test]
+    fn packed_matches_strings_sample() {
+        for pattern in SAMPLE.split("\n\n") {
+            let terrain = Terrain::parse_input(pattern);
+            let packed = PackedTerrain::parse_input(pattern);
+            for smudges in [Smudges::Exactly(0), Smudges::Exactly(1), Smudges::AtMost(3)] {
+                assert_eq!(packed.reflections(smudges), terrain.reflections(smudges));
+                assert_eq!(
+                    packed.find_reflection_with_smudges(smudges),
+                    terrain.find_reflection_with_smudges(smudges)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn packed_matches_strings_wide() {
+        let mut rng = Rng::new(0x2023_1213);
+        for (num_rows, num_cols) in [(3, 5), (9, 63), (10, 64), (7, 65), (12, 130), (130, 9)] {
+            let pattern = random_pattern(&mut rng, num_rows, num_cols);
+            let terrain = Terrain::parse_input(&pattern);
+            let packed = PackedTerrain::parse_input(&pattern);
+            for smudges in [Smudges::Exactly(0), Smudges::Exactly(2), Smudges::AtMost(4)] {
+                assert_eq!(packed.reflections(smudges), terrain.reflections(smudges));
+                assert_eq!(
+                    packed.find_reflection_with_smudges(smudges),
+                    terrain.find_reflection_with_smudges(smudges)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn symmetries_exact() {
+        let terrain = Terrain::parse_input("#..\n.#.\n..#");
+        assert_eq!(
+            terrain.symmetries(Smudges::Exactly(0)),
+            vec![
+                (Symmetry::HalfTurn, 0),
+                (Symmetry::MainDiagonal, 0),
+                (Symmetry::AntiDiagonal, 0),
+            ]
+        );
+
+        let terrain = Terrain::parse_input("#.#\n...\n#.#");
+        assert_eq!(
+            terrain.symmetries(Smudges::Exactly(0)),
+            vec![
+                (Symmetry::HalfTurn, 0),
+                (Symmetry::QuarterTurn, 0),
+                (Symmetry::MainDiagonal, 0),
+                (Symmetry::AntiDiagonal, 0),
+            ]
+        );
+
+        let terrain = Terrain::parse_input("##.\n.##");
+        assert_eq!(
+            terrain.symmetries(Smudges::AtMost(10)),
+            vec![(Symmetry::HalfTurn, 0)]
+        );
+        assert_eq!(terrain.symmetry_smudges(Symmetry::MainDiagonal), None);
+    }
+
+    #[test]
+    fn symmetries_with_smudges() {
+        let terrain = Terrain::parse_input("##.\n...\n#.#");
+        assert_eq!(terrain.symmetry_smudges(Symmetry::HalfTurn), Some(2));
+        assert_eq!(terrain.symmetry_smudges(Symmetry::QuarterTurn), Some(2));
+        assert_eq!(terrain.symmetry_smudges(Symmetry::MainDiagonal), Some(2));
+        assert_eq!(terrain.symmetry_smudges(Symmetry::AntiDiagonal), Some(1));
+
+        let terrain = Terrain::parse_input("#...\n....\n....\n....");
+        assert_eq!(terrain.symmetry_smudges(Symmetry::QuarterTurn), Some(1));
+        assert_eq!(terrain.symmetry_smudges(Symmetry::MainDiagonal), Some(0));
+        assert_eq!(terrain.symmetry_smudges(Symmetry::AntiDiagonal), Some(1));
+        assert_eq!(
+            terrain.symmetries(Smudges::Exactly(1)),
+            vec![
+                (Symmetry::HalfTurn, 1),
+                (Symmetry::QuarterTurn, 1),
+                (Symmetry::AntiDiagonal, 1),
+            ]
+        );
+    }
 }
@@ -1,4 +1,6 @@
-#[derive(Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
+use std::collections::HashMap;
+
+#[derive(Ord, PartialOrd, Eq, PartialEq, Copy, Clone, Hash, Debug)]
 enum Space {
     Round,
     Empty,
@@ -14,6 +16,14 @@ impl Space {
             _ => panic!(),
         }
     }
+
+    fn to_char(self) -> char {
+        match self {
+            Space::Round => 'O',
+            Space::Cube => '#',
+            Space::Empty => '.',
+        }
+    }
 }
 
 fn parse_cols(input: &str) -> Vec<Vec<Space>> {
@@ -45,25 +55,10 @@ fn compress(col: &Vec<Space>, reverse: bool) -> Vec<Space> {
         .join(&Space::Cube)
 }
 
-fn calc_load(spaces: &Vec<Vec<Space>>) -> usize {
-    let num_rows = spaces.len();
-    transpose(spaces)
-        .iter()
-        .map(|col| {
-            col.iter().enumerate().fold(0, |acc, (row, space)| {
-                if matches!(space, Space::Round) {
-                    acc + num_rows - row
-                } else {
-                    acc
-                }
-            })
-        })
-        .sum()
-}
-
 fn part_1(input: &str) -> usize {
-    let spaces = parse_spaces(input);
-    calc_load(&compress_north(&spaces))
+    let mut spaces = parse_spaces(input);
+    tilt(&mut spaces, Tilt::North);
+    load(&spaces, Tilt::North)
 }
 
 fn parse_spaces(input: &str) -> Vec<Vec<Space>> {
@@ -122,20 +117,190 @@ fn spin(spaces: &Vec<Vec<Space>>) -> Vec<Vec<Space>> {
     compress_east(&compress_south(&compress_west(&compress_north(&spaces))))
 }
 
-fn part_2(input: &str) -> usize {
-    let mut spaces = parse_spaces(input);
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum Tilt {
+    North,
+    West,
+    South,
+    East,
+}
 
-    for _ in 0..1000 {
-        spaces = spin(&spaces);
+/// Rolls every round rock along one line of the platform as far as it goes. `pos(k)`
+/// is the position `k` spaces in from the edge the rocks roll towards, and `free`
+/// trails behind the scan at the next space a rock would stop in.
+fn roll<F>(spaces: &mut [Vec<Space>], len: usize, pos: F)
+where
+    F: Fn(usize) -> (usize, usize),
+{
+    let mut free = 0;
+    for k in 0..len {
+        let (row, col) = pos(k);
+        match spaces[row][col] {
+            Space::Cube => free = k + 1,
+            Space::Round => {
+                if free != k {
+                    let (free_row, free_col) = pos(free);
+                    spaces[free_row][free_col] = Space::Round;
+                    spaces[row][col] = Space::Empty;
+                }
+                free += 1;
+            }
+            Space::Empty => {}
+        }
     }
+}
 
-    calc_load(&spaces)
+/// Same result as the matching `compress_*`, but rolls the rocks in place.
+fn tilt(spaces: &mut [Vec<Space>], dir: Tilt) {
+    let (num_rows, num_cols) = (spaces.len(), spaces[0].len());
+    match dir {
+        Tilt::North => (0..num_cols).for_each(|col| roll(spaces, num_rows, |k| (k, col))),
+        Tilt::South => {
+            (0..num_cols).for_each(|col| roll(spaces, num_rows, |k| (num_rows - 1 - k, col)))
+        }
+        Tilt::West => (0..num_rows).for_each(|row| roll(spaces, num_cols, |k| (row, k))),
+        Tilt::East => {
+            (0..num_rows).for_each(|row| roll(spaces, num_cols, |k| (row, num_cols - 1 - k)))
+        }
+    }
+}
+
+impl Tilt {
+    fn from_char(c: char) -> Option<Tilt> {
+        match c {
+            'N' => Some(Tilt::North),
+            'W' => Some(Tilt::West),
+            'S' => Some(Tilt::South),
+            'E' => Some(Tilt::East),
+            _ => None,
+        }
+    }
+}
+
+const SPIN: [Tilt; 4] = [Tilt::North, Tilt::West, Tilt::South, Tilt::East];
+
+/// A character in a tilt program that isn't a direction, at byte `offset`.
+#[derive(Debug, Eq, PartialEq)]
+struct UnknownTilt {
+    offset: usize,
+    symbol: char,
+}
+
+/// Parses a tilt program such as `NNWSE`, ignoring whitespace.
+fn parse_program(program: &str) -> Result<Vec<Tilt>, UnknownTilt> {
+    program
+        .char_indices()
+        .filter(|(_, c)| !c.is_whitespace())
+        .map(|(offset, symbol)| Tilt::from_char(symbol).ok_or(UnknownTilt { offset, symbol }))
+        .collect()
+}
+
+fn run_once(spaces: &mut [Vec<Space>], program: &[Tilt]) {
+    for dir in program {
+        tilt(spaces, *dir);
+    }
+}
+
+fn spin_in_place(spaces: &mut [Vec<Space>]) {
+    run_once(spaces, &SPIN);
+}
+
+/// The load on the `edge` support beams: each round rock weighs as much as its
+/// distance in spaces from the opposite edge, counting its own space.
+fn load(spaces: &[Vec<Space>], edge: Tilt) -> usize {
+    let (num_rows, num_cols) = (spaces.len(), spaces[0].len());
+    spaces
+        .iter()
+        .enumerate()
+        .flat_map(|(row, line)| line.iter().enumerate().map(move |(col, s)| (row, col, s)))
+        .filter(|(_, _, space)| matches!(space, Space::Round))
+        .map(|(row, col, _)| match edge {
+            Tilt::North => num_rows - row,
+            Tilt::South => row + 1,
+            Tilt::West => num_cols - col,
+            Tilt::East => col + 1,
+        })
+        .sum()
+}
+
+fn render(spaces: &[Vec<Space>]) -> String {
+    spaces
+        .iter()
+        .map(|line| line.iter().map(|s| s.to_char()).collect::<String>())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The platform rendered after each tilt of one run of `program`.
+fn trace(spaces: &[Vec<Space>], program: &[Tilt]) -> Vec<String> {
+    let mut spaces = spaces.to_vec();
+    program
+        .iter()
+        .map(|dir| {
+            tilt(&mut spaces, *dir);
+            render(&spaces)
+        })
+        .collect()
+}
+
+/// Running a tilt program over and over eventually repeats a state. `start` is how
+/// many runs happen before the first repeated state, and `period` how many runs it
+/// takes to come back around to it.
+#[derive(Debug, Eq, PartialEq)]
+struct Cycle {
+    start: usize,
+    period: usize,
+}
+
+/// Runs `program` until a state repeats, returning the cycle and every state seen,
+/// where `states[i]` is the platform after `i` runs.
+fn find_cycle(spaces: &[Vec<Space>], program: &[Tilt]) -> (Cycle, Vec<Vec<Vec<Space>>>) {
+    let mut seen = HashMap::new();
+    let mut states = vec![spaces.to_vec()];
+    loop {
+        let current = states.last().unwrap();
+        if let Some(start) = seen.get(current) {
+            let cycle = Cycle {
+                start: *start,
+                period: states.len() - 1 - start,
+            };
+            states.pop();
+            return (cycle, states);
+        }
+        seen.insert(current.clone(), states.len() - 1);
+        let mut next = current.clone();
+        run_once(&mut next, program);
+        states.push(next);
+    }
+}
+
+/// The platform after running `program` `repeats` times, jumping ahead once the
+/// states start repeating.
+fn run_program(spaces: &[Vec<Space>], program: &[Tilt], repeats: usize) -> Vec<Vec<Space>> {
+    let (cycle, mut states) = find_cycle(spaces, program);
+    let idx = if repeats < states.len() {
+        repeats
+    } else {
+        cycle.start + (repeats - cycle.start) % cycle.period
+    };
+    states.swap_remove(idx)
+}
+
+/// The platform after `num_cycles` spin cycles.
+fn spin_n(spaces: &[Vec<Space>], num_cycles: usize) -> Vec<Vec<Space>> {
+    run_program(spaces, &SPIN, num_cycles)
+}
+
+fn part_2(input: &str) -> usize {
+    let spaces = parse_spaces(input);
+    load(&spin_n(&spaces, 1000000000), Tilt::North)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::aoc_2023::data::day_14::*;
+    use crate::aoc_2023::Rng;
 
     #[test]
     fn part_1_sample() {
@@ -158,6 +323,151 @@ mod tests {
     #[test]
     fn part_2_input() {
         let result = part_2(INPUT);
-        dbg!(result);
+        // No answer is recorded for this input, so check against the reference `spin`,
+        // finding the repeat by remembering every platform instead of `find_cycle`.
+        let mut seen = HashMap::new();
+        let mut platforms = Vec::new();
+        let mut spaces = parse_spaces(INPUT);
+        while !seen.contains_key(&spaces) {
+            seen.insert(spaces.clone(), platforms.len());
+            platforms.push(spaces.clone());
+            spaces = spin(&spaces);
+        }
+        let start = seen[&spaces];
+        let landed = &platforms[start + (1000000000 - start) % (platforms.len() - start)];
+        assert_eq!(result, load(landed, Tilt::North));
+    }
+
+    #[test]
+    fn find_cycle_sample() {
+        let spaces = parse_spaces(SAMPLE);
+        let (cycle, states) = find_cycle(&spaces, &SPIN);
+        assert_eq!(
+            cycle,
+            Cycle {
+                start: 3,
+                period: 7
+            }
+        );
+        assert_eq!(states.len(), 10);
+        assert_eq!(states[0], spaces);
+    }
+
+    #[test]
+    fn spin_n_matches_spinning() {
+        let spaces = parse_spaces(SAMPLE);
+        let mut spun = spaces.clone();
+        for num_cycles in 0..30 {
+            assert_eq!(spin_n(&spaces, num_cycles), spun);
+            spun = spin(&spun);
+        }
+    }
+
+    /// The reference `transpose` only handles square platforms.
+    fn random_platform(rng: &mut Rng, size: usize) -> Vec<Vec<Space>> {
+        (0..size)
+            .map(|_| {
+                (0..size)
+                    .map(|_| match rng.below(5) {
+                        0 | 1 => Space::Round,
+                        2 => Space::Cube,
+                        _ => Space::Empty,
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tilt_matches_compress() {
+        let mut rng = Rng::new(0x2023_1214);
+        let platforms = (1..20)
+            .map(|size| random_platform(&mut rng, size))
+            .chain([parse_spaces(SAMPLE)]);
+        for spaces in platforms {
+            for dir in [Tilt::North, Tilt::West, Tilt::South, Tilt::East] {
+                let reference = match dir {
+                    Tilt::North => compress_north(&spaces),
+                    Tilt::West => compress_west(&spaces),
+                    Tilt::South => compress_south(&spaces),
+                    Tilt::East => compress_east(&spaces),
+                };
+                let mut tilted = spaces.clone();
+                tilt(&mut tilted, dir);
+                assert_eq!(tilted, reference);
+            }
+
+            let (mut spun, mut reference) = (spaces.clone(), spaces.clone());
+            for _ in 0..10 {
+                spin_in_place(&mut spun);
+                reference = spin(&reference);
+                assert_eq!(spun, reference);
+            }
+        }
+    }
+
+    #[test]
+    fn load_on_each_edge() {
+        let spaces = parse_spaces(SAMPLE);
+        assert_eq!(load(&spaces, Tilt::North), 104);
+
+        let spaces = parse_spaces("O.\n.#");
+        assert_eq!(load(&spaces, Tilt::North), 2);
+        assert_eq!(load(&spaces, Tilt::South), 1);
+        assert_eq!(load(&spaces, Tilt::West), 2);
+        assert_eq!(load(&spaces, Tilt::East), 1);
+    }
+
+    #[test]
+    fn run_program_matches_tilting() {
+        let spaces = parse_spaces(SAMPLE);
+        assert_eq!(parse_program("N W S E").unwrap(), SPIN);
+        assert_eq!(run_program(&spaces, &SPIN, 1000), spin_n(&spaces, 1000));
+
+        let program = parse_program("NNWSE").unwrap();
+        let mut tilted = spaces.clone();
+        for repeats in 0..20 {
+            assert_eq!(run_program(&spaces, &program, repeats), tilted);
+            run_once(&mut tilted, &program);
+        }
+        let far = run_program(&spaces, &program, 1000000000);
+        assert_eq!(far, run_program(&spaces, &SPIN, 1000000000));
+    }
+
+    #[test]
+    fn parse_program_errors() {
+        assert_eq!(
+            parse_program("NNX"),
+            Err(UnknownTilt {
+                offset: 2,
+                symbol: 'X'
+            })
+        );
+        assert_eq!(
+            parse_program("N W s E"),
+            Err(UnknownTilt {
+                offset: 4,
+                symbol: 's'
+            })
+        );
+        assert_eq!(parse_program(" \n"), Ok(vec![]));
+    }
+
+    #[test]
+    fn trace_sample() {
+        let spaces = parse_spaces(SAMPLE);
+        let states = trace(&spaces, &parse_program("NE").unwrap());
+        assert_eq!(states.len(), 2);
+        assert!(states[0].starts_with("OOOO.#.O..\nOO..#....#"));
+        assert!(states[1].starts_with(".OOOO#...O\n..OO#....#"));
+        assert_eq!(render(&spaces), SAMPLE);
+    }
+
+    #[test]
+    fn parts_on_wide_platform() {
+        assert_eq!(part_1("O.#O\n.O.."), 6);
+        assert_eq!(part_1("O\n.\nO\n#\nO"), 5 + 4 + 1);
+        // Settles after two cycles into ".O#.\n..OO".
+        assert_eq!(part_2("O.#O\n.O.."), 4);
     }
 }
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::num::Wrapping;
 
 fn compute_hash(input: &str) -> u8 {
@@ -8,8 +10,37 @@ fn compute_hash(input: &str) -> u8 {
         .0
 }
 
+/// Splits the initialization sequence on commas, ignoring newlines, and pairs
+/// each step with the byte offset it starts at in `input`.
+fn steps(input: &str) -> Vec<(usize, String)> {
+    let mut steps = Vec::new();
+    let mut start = 0;
+    let mut current = String::new();
+    for (offset, c) in input.char_indices() {
+        match c {
+            ',' => {
+                steps.push((start, std::mem::take(&mut current)));
+                start = offset + 1;
+            }
+            '\n' | '\r' => {
+                if current.is_empty() {
+                    start = offset + 1;
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() || !steps.is_empty() {
+        steps.push((start, current));
+    }
+    steps
+}
+
 fn part_1(input: &str) -> usize {
-    input.split(',').map(|s| compute_hash(s) as usize).sum()
+    steps(input)
+        .iter()
+        .map(|(_, step)| compute_hash(step) as usize)
+        .sum()
 }
 
 #[derive(Clone)]
@@ -23,73 +54,337 @@ enum Operation {
     Remove(String),
 }
 
+/// A step that isn't `label=focal` or `label-`, with the byte offset of the step.
+#[derive(Debug, Eq, PartialEq)]
+enum ParseError {
+    EmptyStep { offset: usize },
+    MissingOperation { offset: usize, step: String },
+    InvalidLabel { offset: usize, label: String },
+    InvalidFocal { offset: usize, focal: String },
+}
+
+/// Labels are one or more lowercase letters.
+fn parse_label(offset: usize, label: &str) -> Result<String, ParseError> {
+    if label.is_empty() || !label.chars().all(|c| c.is_ascii_lowercase()) {
+        return Err(ParseError::InvalidLabel {
+            offset,
+            label: String::from(label),
+        });
+    }
+    Ok(String::from(label))
+}
+
+fn parse_step(offset: usize, step: &str) -> Result<Operation, ParseError> {
+    if step.is_empty() {
+        return Err(ParseError::EmptyStep { offset });
+    }
+    if let Some(label) = step.strip_suffix('-') {
+        return Ok(Operation::Remove(parse_label(offset, label)?));
+    }
+    let Some((label, focal)) = step.split_once('=') else {
+        return Err(ParseError::MissingOperation {
+            offset,
+            step: String::from(step),
+        });
+    };
+    let label = parse_label(offset, label)?;
+    // `parse` alone would also take a leading '+'.
+    let focal = match focal.parse::<u8>() {
+        Ok(value) if focal.chars().all(|c| c.is_ascii_digit()) => value,
+        _ => {
+            return Err(ParseError::InvalidFocal {
+                offset,
+                focal: String::from(focal),
+            })
+        }
+    };
+    Ok(Operation::Add(Lens { label, focal }))
+}
+
+/// Parses every step, collecting all the malformed ones rather than stopping at the first.
+fn try_parse_operations(input: &str) -> Result<Vec<Operation>, Vec<ParseError>> {
+    let mut operations = Vec::new();
+    let mut errors = Vec::new();
+    for (offset, step) in steps(input) {
+        match parse_step(offset, &step) {
+            Ok(op) => operations.push(op),
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    Ok(operations)
+}
+
 fn parse_operations(input: &str) -> Vec<Operation> {
-    input
-        .split(',')
-        .map(|s| match s.chars().last().unwrap() {
-            '-' => Operation::Remove(String::from(&s[..s.len() - 1])),
-            _ => Operation::Add(Lens {
-                label: String::from(&s[..s.len() - 2]),
-                focal: String::from(s.chars().last().unwrap())
-                    .parse::<u8>()
-                    .unwrap(),
-            }),
-        })
-        .collect::<Vec<Operation>>()
+    match try_parse_operations(input) {
+        Ok(operations) => operations,
+        Err(errors) => panic!("Malformed initialization sequence: {:?}", errors),
+    }
 }
 
-fn total_focus_power(boxes: &Vec<Vec<Lens>>) -> usize {
-    boxes
-        .iter()
-        .enumerate()
-        .map(|(box_idx, b)| {
-            b.iter()
-                .enumerate()
-                .map(|(slot_idx, lens)| (box_idx + 1) * (slot_idx + 1) * (lens.focal as usize))
-                .sum::<usize>()
-        })
-        .sum()
+/// The HASHMAP procedure as a reusable map: keys are hashed into numbered boxes,
+/// and each box keeps its entries in the order they were first inserted.
+///
+/// Instead of scanning a box for a key, an index records which box and position
+/// every key is at. Removing leaves a gap that iteration skips, and a box is
+/// compacted once it holds more gaps than entries.
+struct LensMap<K, V> {
+    boxes: Vec<Vec<Option<(K, V)>>>,
+    gaps: Vec<usize>,
+    index: HashMap<K, (usize, usize)>,
+    hash: fn(&K) -> usize,
 }
 
-fn part_2(input: &str) -> usize {
-    let mut boxes = (0..256).map(|_| Vec::new()).collect::<Vec<Vec<Lens>>>();
+impl<V> LensMap<String, V> {
+    /// 256 boxes chosen by `compute_hash`, as in the puzzle.
+    fn new() -> Self {
+        LensMap::with_hash(256, |label| compute_hash(label) as usize)
+    }
+}
 
-    parse_operations(input)
-        .iter()
-        .map(|op| match op {
-            Operation::Add(Lens { label, focal: _ }) => (op, compute_hash(label)),
-            Operation::Remove(label) => (op, compute_hash(label)),
-        })
-        .for_each(|(op, h)| match op {
-            Operation::Add(lens) => {
-                match boxes[h as usize]
+impl<K: Hash + Eq + Clone, V> LensMap<K, V> {
+    /// A map with `num_boxes` boxes, putting each key in box `hash(key) % num_boxes`.
+    fn with_hash(num_boxes: usize, hash: fn(&K) -> usize) -> Self {
+        LensMap {
+            boxes: (0..num_boxes).map(|_| Vec::new()).collect(),
+            gaps: vec![0; num_boxes],
+            index: HashMap::new(),
+            hash,
+        }
+    }
+
+    fn box_of(&self, key: &K) -> usize {
+        (self.hash)(key) % self.boxes.len()
+    }
+
+    /// Replaces the value in place if the key is already present, otherwise adds
+    /// the entry behind everything else in its box. Returns the replaced value.
+    fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some((box_idx, pos)) = self.index.get(&key) {
+            let (_, old) = self.boxes[*box_idx][*pos].as_mut().unwrap();
+            return Some(std::mem::replace(old, value));
+        }
+        let box_idx = self.box_of(&key);
+        self.index
+            .insert(key.clone(), (box_idx, self.boxes[box_idx].len()));
+        self.boxes[box_idx].push(Some((key, value)));
+        None
+    }
+
+    fn remove(&mut self, key: &K) -> Option<V> {
+        let (box_idx, pos) = self.index.remove(key)?;
+        let (_, value) = self.boxes[box_idx][pos].take().unwrap();
+        self.gaps[box_idx] += 1;
+        if self.gaps[box_idx] * 2 > self.boxes[box_idx].len() {
+            self.compact(box_idx);
+        }
+        Some(value)
+    }
+
+    fn compact(&mut self, box_idx: usize) {
+        self.boxes[box_idx].retain(Option::is_some);
+        self.gaps[box_idx] = 0;
+        for (pos, (key, _)) in self.boxes[box_idx].iter().flatten().enumerate() {
+            self.index.insert(key.clone(), (box_idx, pos));
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<&V> {
+        let (box_idx, pos) = self.index.get(key)?;
+        self.boxes[*box_idx][*pos].as_ref().map(|(_, value)| value)
+    }
+
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Every entry as (box, slot, key, value) in box order, then slot order.
+    fn iter(&self) -> impl Iterator<Item = (usize, usize, &K, &V)> {
+        self.boxes
+            .iter()
+            .enumerate()
+            .flat_map(|(box_idx, entries)| {
+                entries
                     .iter()
+                    .flatten()
                     .enumerate()
-                    .find(|(_, l)| l.label == lens.label)
-                {
-                    Some((idx, _)) => boxes[h as usize][idx] = lens.clone(),
-                    None => boxes[h as usize].push(lens.clone()),
-                }
+                    .map(move |(slot_idx, (key, value))| (box_idx, slot_idx, key, value))
+            })
+    }
+
+    fn focusing_power(&self) -> usize
+    where
+        V: Copy + Into<usize>,
+    {
+        self.iter()
+            .map(|(box_idx, slot_idx, _, value)| (box_idx + 1) * (slot_idx + 1) * (*value).into())
+            .sum()
+    }
+}
+
+fn part_2(input: &str) -> usize {
+    let mut boxes = LensMap::new();
+    parse_operations(input).into_iter().for_each(|op| {
+        apply(&mut boxes, op);
+    });
+    boxes.focusing_power()
+}
+
+/// What an operation did to the box it touched.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Effect {
+    Added,
+    Replaced,
+    Removed,
+    /// A removal of a label that wasn't in its box.
+    Unchanged,
+}
+
+impl Effect {
+    fn to_str(self) -> &'static str {
+        match self {
+            Effect::Added => "added",
+            Effect::Replaced => "replaced",
+            Effect::Removed => "removed",
+            Effect::Unchanged => "unchanged",
+        }
+    }
+}
+
+impl Operation {
+    fn label(&self) -> &String {
+        match self {
+            Operation::Add(lens) => &lens.label,
+            Operation::Remove(label) => label,
+        }
+    }
+
+    /// The operation as it is written in the initialization sequence.
+    fn to_step(&self) -> String {
+        match self {
+            Operation::Add(lens) => format!("{}={}", lens.label, lens.focal),
+            Operation::Remove(label) => format!("{}-", label),
+        }
+    }
+}
+
+/// Applies `op`, returning the box it touched and what it did there.
+fn apply(boxes: &mut LensMap<String, u8>, op: Operation) -> (usize, Effect) {
+    let box_idx = boxes.box_of(op.label());
+    let effect = match op {
+        Operation::Add(Lens { label, focal }) => match boxes.insert(label, focal) {
+            Some(_) => Effect::Replaced,
+            None => Effect::Added,
+        },
+        Operation::Remove(label) => match boxes.remove(&label) {
+            Some(_) => Effect::Removed,
+            None => Effect::Unchanged,
+        },
+    };
+    (box_idx, effect)
+}
+
+/// The non-empty boxes, one per line, as in `Box 3: [ot 7] [ab 5]`.
+fn render_boxes(boxes: &LensMap<String, u8>) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut current = None;
+    for (box_idx, _, label, focal) in boxes.iter() {
+        if current != Some(box_idx) {
+            current = Some(box_idx);
+            lines.push(format!("Box {}:", box_idx));
+        }
+        lines
+            .last_mut()
+            .unwrap()
+            .push_str(&format!(" [{} {}]", label, focal));
+    }
+    lines.join("\n")
+}
+
+/// One step of an initialization sequence, with the boxes rendered after it.
+#[derive(Debug, Eq, PartialEq)]
+struct Step {
+    step: String,
+    box_idx: usize,
+    effect: Effect,
+    boxes: String,
+}
+
+impl Step {
+    fn render(&self) -> String {
+        format!(
+            "After \"{}\" (box {}, {}):\n{}",
+            self.step,
+            self.box_idx,
+            self.effect.to_str(),
+            self.boxes
+        )
+    }
+}
+
+/// Replays the initialization sequence one operation at a time.
+fn trace(input: &str) -> (Vec<Step>, LensMap<String, u8>) {
+    let mut boxes = LensMap::new();
+    let steps = parse_operations(input)
+        .into_iter()
+        .map(|op| {
+            let step = op.to_step();
+            let (box_idx, effect) = apply(&mut boxes, op);
+            Step {
+                step,
+                box_idx,
+                effect,
+                boxes: render_boxes(&boxes),
             }
-            Operation::Remove(label) => match boxes[h as usize]
-                .iter()
-                .enumerate()
-                .find(|(_, l)| l.label == *label)
-            {
-                Some((idx, _)) => {
-                    boxes[h as usize].remove(idx);
-                }
-                None => (),
-            },
-        });
+        })
+        .collect();
+    (steps, boxes)
+}
 
-    total_focus_power(&boxes)
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The non-empty boxes as JSON, e.g.
+/// `{"boxes":[{"box":3,"lenses":[{"label":"ot","focal":7},{"label":"ab","focal":5}]}]}`.
+fn to_json(boxes: &LensMap<String, u8>) -> String {
+    let mut groups: Vec<(usize, Vec<String>)> = Vec::new();
+    for (box_idx, _, label, focal) in boxes.iter() {
+        if groups.last().map(|(idx, _)| *idx) != Some(box_idx) {
+            groups.push((box_idx, Vec::new()));
+        }
+        groups.last_mut().unwrap().1.push(format!(
+            "{{\"label\":{},\"focal\":{}}}",
+            json_string(label),
+            focal
+        ));
+    }
+    let groups = groups
+        .iter()
+        .map(|(box_idx, lenses)| {
+            format!("{{\"box\":{},\"lenses\":[{}]}}", box_idx, lenses.join(","))
+        })
+        .collect::<Vec<String>>();
+    format!("{{\"boxes\":[{}]}}", groups.join(","))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::aoc_2023::data::day_15::*;
     use crate::aoc_2023::day_15::*;
+    use crate::aoc_2023::Rng;
 
     #[test]
     fn part_1_sample() {
@@ -114,4 +409,200 @@ mod tests {
         let result = part_2(INPUT);
         assert_eq!(result, 247933);
     }
+
+    /// The puzzle's procedure written as plainly as possible, with a linear scan
+    /// of each box, to check `LensMap` against.
+    struct Model {
+        boxes: Vec<Vec<(String, u8)>>,
+        hash: fn(&String) -> usize,
+    }
+
+    impl Model {
+        fn insert(&mut self, label: &str, focal: u8) {
+            let lenses = &mut self.boxes[(self.hash)(&label.to_string()) % 256];
+            match lenses.iter().position(|(l, _)| l == label) {
+                Some(idx) => lenses[idx].1 = focal,
+                None => lenses.push((label.to_string(), focal)),
+            }
+        }
+
+        fn remove(&mut self, label: &str) {
+            let lenses = &mut self.boxes[(self.hash)(&label.to_string()) % 256];
+            lenses.retain(|(l, _)| l != label);
+        }
+
+        fn entries(&self) -> Vec<(usize, usize, String, u8)> {
+            self.boxes
+                .iter()
+                .enumerate()
+                .flat_map(|(box_idx, lenses)| {
+                    lenses
+                        .iter()
+                        .enumerate()
+                        .map(move |(slot_idx, (l, f))| (box_idx, slot_idx, l.clone(), *f))
+                })
+                .collect()
+        }
+    }
+
+    fn check_against_model(hash: fn(&String) -> usize) {
+        let mut rng = Rng::new(0x2023_1215);
+        let labels = ["rn", "cm", "qp", "pc", "ot", "ab", "a", "zz", "hello", "x"];
+        let mut map = LensMap::with_hash(256, hash);
+        let mut model = Model {
+            boxes: vec![Vec::new(); 256],
+            hash,
+        };
+        for _ in 0..5000 {
+            let label = labels[rng.below(labels.len())];
+            if rng.below(3) == 0 {
+                map.remove(&label.to_string());
+                model.remove(label);
+            } else {
+                let focal = (rng.below(9) + 1) as u8;
+                map.insert(label.to_string(), focal);
+                model.insert(label, focal);
+            }
+            let entries = map
+                .iter()
+                .map(|(b, s, l, f)| (b, s, l.clone(), *f))
+                .collect::<Vec<(usize, usize, String, u8)>>();
+            assert_eq!(entries, model.entries());
+            assert_eq!(map.len(), entries.len());
+        }
+    }
+
+    #[test]
+    fn lens_map_matches_model() {
+        check_against_model(|label| compute_hash(label) as usize);
+        // Everything in one box exercises slot order and compaction the hardest.
+        check_against_model(|_| 3);
+    }
+
+    #[test]
+    fn lens_map_sample() {
+        let mut boxes = LensMap::new();
+        assert_eq!(boxes.insert(String::from("rn"), 1u8), None);
+        assert_eq!(boxes.insert(String::from("qp"), 3), None);
+        assert_eq!(boxes.insert(String::from("cm"), 2), None);
+        assert_eq!(boxes.remove(&String::from("qp")), Some(3));
+        assert_eq!(boxes.remove(&String::from("qp")), None);
+        assert_eq!(boxes.insert(String::from("rn"), 7), Some(1));
+        assert_eq!(boxes.get(&String::from("rn")), Some(&7));
+        assert_eq!(
+            boxes.iter().map(|(b, s, _, _)| (b, s)).collect::<Vec<_>>(),
+            vec![(0, 0), (0, 1)]
+        );
+        assert_eq!(boxes.focusing_power(), 7 + 2 * 2);
+    }
+
+    #[test]
+    fn trace_sample() {
+        let (steps, boxes) = trace(SAMPLE);
+        assert_eq!(steps.len(), 11);
+        assert_eq!(
+            steps[0].render(),
+            "After \"rn=1\" (box 0, added):\nBox 0: [rn 1]"
+        );
+        assert_eq!(
+            steps[1].render(),
+            "After \"cm-\" (box 0, unchanged):\nBox 0: [rn 1]"
+        );
+        assert_eq!(
+            steps[3].render(),
+            "After \"cm=2\" (box 0, added):\nBox 0: [rn 1] [cm 2]\nBox 1: [qp 3]"
+        );
+        assert_eq!((steps[4].box_idx, steps[4].effect), (1, Effect::Removed));
+        assert_eq!((steps[10].box_idx, steps[10].effect), (3, Effect::Replaced));
+        assert_eq!(
+            steps[10].boxes,
+            "Box 0: [rn 1] [cm 2]\nBox 3: [ot 7] [ab 5] [pc 6]"
+        );
+        assert_eq!(boxes.focusing_power(), 145);
+    }
+
+    #[test]
+    fn to_json_sample() {
+        let (_, boxes) = trace(SAMPLE);
+        assert_eq!(
+            to_json(&boxes),
+            concat!(
+                "{\"boxes\":[",
+                "{\"box\":0,\"lenses\":[{\"label\":\"rn\",\"focal\":1},{\"label\":\"cm\",\"focal\":2}]},",
+                "{\"box\":3,\"lenses\":[{\"label\":\"ot\",\"focal\":7},",
+                "{\"label\":\"ab\",\"focal\":5},{\"label\":\"pc\",\"focal\":6}]}",
+                "]}"
+            )
+        );
+        assert_eq!(to_json(&LensMap::new()), "{\"boxes\":[]}");
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+
+    #[test]
+    fn steps_ignore_newlines() {
+        assert_eq!(part_1("HASH\n"), 52);
+        assert_eq!(part_1(&format!("{}\n", SAMPLE)), 1320);
+        assert_eq!(part_1("rn=1,c\nm-"), part_1("rn=1,cm-"));
+        assert_eq!(
+            steps("ab=1,\ncd-\n"),
+            vec![(0, String::from("ab=1")), (6, String::from("cd-"))]
+        );
+        assert!(steps("\n").is_empty());
+    }
+
+    #[test]
+    fn parse_multi_digit_focal() {
+        let (steps, boxes) = trace("rn=12,cm=255,rn=3\n");
+        assert_eq!(steps[0].boxes, "Box 0: [rn 12]");
+        assert_eq!(
+            steps[2].render(),
+            "After \"rn=3\" (box 0, replaced):\nBox 0: [rn 3] [cm 255]"
+        );
+        assert_eq!(boxes.focusing_power(), 3 + 2 * 255);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            try_parse_operations("ab=1,,cd-,").err(),
+            Some(vec![
+                ParseError::EmptyStep { offset: 5 },
+                ParseError::EmptyStep { offset: 10 },
+            ])
+        );
+        assert_eq!(
+            try_parse_operations("ab=1,cd,Ef=2,=3,gh=,ij=+4,kl=256,m-n-").err(),
+            Some(vec![
+                ParseError::MissingOperation {
+                    offset: 5,
+                    step: String::from("cd")
+                },
+                ParseError::InvalidLabel {
+                    offset: 8,
+                    label: String::from("Ef")
+                },
+                ParseError::InvalidLabel {
+                    offset: 13,
+                    label: String::from("")
+                },
+                ParseError::InvalidFocal {
+                    offset: 16,
+                    focal: String::from("")
+                },
+                ParseError::InvalidFocal {
+                    offset: 20,
+                    focal: String::from("+4")
+                },
+                ParseError::InvalidFocal {
+                    offset: 26,
+                    focal: String::from("256")
+                },
+                ParseError::InvalidLabel {
+                    offset: 33,
+                    label: String::from("m-n")
+                },
+            ])
+        );
+        assert_eq!(try_parse_operations("").map(|ops| ops.len()), Ok(0));
+    }
 }
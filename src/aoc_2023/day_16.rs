@@ -1,38 +1,167 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
-enum Space {
-    Empty,
-    MirrorFwd,
-    MirrorBck,
-    SplitterHor,
-    SplitterVert,
+/// A tile of a layout: the symbol it is drawn with and, for each direction a beam
+/// can arrive in (indexed by `Dir::index`), the directions it leaves in.
+struct Space {
+    symbol: char,
+    outputs: [Vec<Dir>; 4],
 }
 
 impl Space {
-    fn from_char(c: char) -> Space {
-        match c {
-            '.' => Space::Empty,
-            '/' => Space::MirrorFwd,
-            '\\' => Space::MirrorBck,
-            '-' => Space::SplitterHor,
-            '|' => Space::SplitterVert,
-            _ => panic!(),
+    fn new(symbol: char, mapping: impl Fn(Dir) -> Vec<Dir>) -> Space {
+        Space {
+            symbol,
+            outputs: DIRS.map(mapping),
         }
     }
+
+    fn to_char(&self) -> char {
+        self.symbol
+    }
+
+    /// Whether every beam passes straight through, as through empty space.
+    fn is_empty(&self) -> bool {
+        DIRS.iter().all(|dir| self.outputs[dir.index()] == [*dir])
+    }
+}
+
+/// Symbols that renderings draw beams and energized tiles with, so no element
+/// may use them.
+const RESERVED_SYMBOLS: &str = "^v<>#0123456789";
+
+/// The elements a layout can be built from, by symbol. Layouts share them rather
+/// than keeping a copy per tile.
+struct Elements {
+    spaces: HashMap<char, Rc<Space>>,
+}
+
+impl Elements {
+    /// The elements of the puzzle: empty space, mirrors and splitters.
+    fn standard() -> Elements {
+        let mut elements = Elements {
+            spaces: HashMap::new(),
+        };
+        elements.insert('.', |dir| vec![dir]);
+        elements.insert('/', |dir| match dir {
+            Dir::Up => vec![Dir::Right],
+            Dir::Down => vec![Dir::Left],
+            Dir::Right => vec![Dir::Up],
+            Dir::Left => vec![Dir::Down],
+        });
+        elements.insert('\\', |dir| match dir {
+            Dir::Up => vec![Dir::Left],
+            Dir::Down => vec![Dir::Right],
+            Dir::Right => vec![Dir::Down],
+            Dir::Left => vec![Dir::Up],
+        });
+        elements.insert('|', |dir| match dir {
+            Dir::Up | Dir::Down => vec![dir],
+            Dir::Right | Dir::Left => vec![Dir::Up, Dir::Down],
+        });
+        elements.insert('-', |dir| match dir {
+            Dir::Right | Dir::Left => vec![dir],
+            Dir::Up | Dir::Down => vec![Dir::Left, Dir::Right],
+        });
+        elements
+    }
+
+    /// The standard elements plus:
+    /// - `X`, an absorber that stops every beam,
+    /// - `N`, `S`, `W` and `E`, one-way gates that only let through beams
+    ///   travelling north, south, west or east,
+    /// - `*`, a prism that lets a beam through and also splits it out to both sides.
+    fn extended() -> Elements {
+        let mut elements = Elements::standard();
+        elements.insert('X', |_| vec![]);
+        for (symbol, gate) in [
+            ('N', Dir::Up),
+            ('S', Dir::Down),
+            ('W', Dir::Left),
+            ('E', Dir::Right),
+        ] {
+            elements.insert(
+                symbol,
+                move |dir| {
+                    if dir == gate {
+                        vec![dir]
+                    } else {
+                        vec![]
+                    }
+                },
+            );
+        }
+        elements.insert('*', |dir| match dir {
+            Dir::Up | Dir::Down => vec![Dir::Left, dir, Dir::Right],
+            Dir::Right | Dir::Left => vec![Dir::Up, dir, Dir::Down],
+        });
+        elements
+    }
+
+    /// Adds an element, or replaces the one drawn with the same symbol.
+    fn define(
+        &mut self,
+        symbol: char,
+        mapping: impl Fn(Dir) -> Vec<Dir>,
+    ) -> Result<(), ReservedSymbol> {
+        if RESERVED_SYMBOLS.contains(symbol) {
+            return Err(ReservedSymbol { symbol });
+        }
+        self.insert(symbol, mapping);
+        Ok(())
+    }
+
+    /// `define` for the built-in elements, whose symbols are known not to be reserved.
+    fn insert(&mut self, symbol: char, mapping: impl Fn(Dir) -> Vec<Dir>) {
+        self.spaces
+            .insert(symbol, Rc::new(Space::new(symbol, mapping)));
+    }
+
+    fn get(&self, symbol: char) -> Option<&Rc<Space>> {
+        self.spaces.get(&symbol)
+    }
+}
+
+/// A symbol an element can't be drawn with, because renderings use it.
+#[derive(Debug, Eq, PartialEq)]
+struct ReservedSymbol {
+    symbol: char,
+}
+
+/// A symbol in a layout that isn't one of its elements.
+#[derive(Debug, Eq, PartialEq)]
+struct UnknownElement {
+    row: usize,
+    col: usize,
+    symbol: char,
 }
 
 struct Layout {
-    spaces: Vec<Vec<Space>>,
+    spaces: Vec<Vec<Rc<Space>>>,
 }
 
 impl Layout {
     fn from_input(input: &str) -> Layout {
-        Layout {
-            spaces: input
-                .lines()
-                .map(|line| line.chars().map(Space::from_char).collect::<Vec<Space>>())
-                .collect::<Vec<Vec<Space>>>(),
-        }
+        Layout::parse(input, &Elements::standard()).unwrap()
+    }
+
+    fn parse(input: &str, elements: &Elements) -> Result<Layout, UnknownElement> {
+        let spaces = input
+            .lines()
+            .enumerate()
+            .map(|(row, line)| {
+                line.chars()
+                    .enumerate()
+                    .map(|(col, symbol)| {
+                        elements
+                            .get(symbol)
+                            .cloned()
+                            .ok_or(UnknownElement { row, col, symbol })
+                    })
+                    .collect::<Result<Vec<Rc<Space>>, UnknownElement>>()
+            })
+            .collect::<Result<Vec<Vec<Rc<Space>>>, UnknownElement>>()?;
+        Ok(Layout { spaces })
     }
 }
 
@@ -44,7 +173,27 @@ enum Dir {
     Right,
 }
 
+const DIRS: [Dir; 4] = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+
 impl Dir {
+    fn index(&self) -> usize {
+        match self {
+            Dir::Up => 0,
+            Dir::Down => 1,
+            Dir::Left => 2,
+            Dir::Right => 3,
+        }
+    }
+
+    fn to_char(self) -> char {
+        match self {
+            Dir::Up => '^',
+            Dir::Down => 'v',
+            Dir::Left => '<',
+            Dir::Right => '>',
+        }
+    }
+
     fn step(&self, pos: &Pos, layout: &Layout) -> Option<Pos> {
         match self {
             Dir::Up => {
@@ -82,56 +231,31 @@ impl Dir {
 type Pos = (usize, usize, Dir);
 
 fn step(start: &Pos, layout: &Layout) -> Vec<Pos> {
-    let next_pos = match layout.spaces[start.0][start.1] {
-        Space::Empty => vec![start.2.step(start, layout)],
-        Space::MirrorFwd => match start.2 {
-            Dir::Up => vec![Dir::Right.step(start, layout)],
-            Dir::Down => vec![Dir::Left.step(start, layout)],
-            Dir::Right => vec![Dir::Up.step(start, layout)],
-            Dir::Left => vec![Dir::Down.step(start, layout)],
-        },
-        Space::MirrorBck => match start.2 {
-            Dir::Up => vec![Dir::Left.step(start, layout)],
-            Dir::Down => vec![Dir::Right.step(start, layout)],
-            Dir::Right => vec![Dir::Down.step(start, layout)],
-            Dir::Left => vec![Dir::Up.step(start, layout)],
-        },
-        Space::SplitterVert => match start.2 {
-            Dir::Up | Dir::Down => vec![start.2.step(start, layout)],
-            Dir::Right | Dir::Left => {
-                vec![Dir::Up.step(start, layout), Dir::Down.step(start, layout)]
-            }
-        },
-        Space::SplitterHor => match start.2 {
-            Dir::Right | Dir::Left => vec![start.2.step(start, layout)],
-            Dir::Up | Dir::Down => vec![
-                Dir::Left.step(start, layout),
-                Dir::Right.step(start, layout),
-            ],
-        },
-    };
-    return next_pos
-        .into_iter()
-        .filter_map(|pos| pos)
-        .collect::<Vec<Pos>>();
+    let next_pos = layout.spaces[start.0][start.1].outputs[start.2.index()]
+        .iter()
+        .map(|dir| dir.step(start, layout));
+    return next_pos.flatten().collect::<Vec<Pos>>();
 }
 
-fn num_tiles(layout: &Layout, start: &Pos) -> usize {
+/// Every beam state reached by a beam entering at `start`.
+fn beam_states(layout: &Layout, start: &Pos) -> HashSet<Pos> {
     let mut visited = HashSet::new();
-    let mut next = vec![start.clone()];
+    let mut next = vec![*start];
 
-    while !next.is_empty() {
-        let pos = next.pop().unwrap();
-        visited.insert(pos.clone());
-        let next_pos = step(&pos, &layout);
-        for p in next_pos.iter() {
-            if !visited.contains(p) {
-                next.push(p.clone());
+    while let Some(pos) = next.pop() {
+        visited.insert(pos);
+        for p in step(&pos, layout) {
+            if !visited.contains(&p) {
+                next.push(p);
             }
         }
     }
 
-    return visited
+    visited
+}
+
+fn num_tiles(layout: &Layout, start: &Pos) -> usize {
+    return beam_states(layout, start)
         .iter()
         .map(|pos| (pos.0, pos.1))
         .collect::<HashSet<(usize, usize)>>()
@@ -143,27 +267,356 @@ fn part_1(input: &str) -> usize {
     num_tiles(&layout, &(0, 0, Dir::Right))
 }
 
-fn part_2(input: &str) -> usize {
-    let layout = Layout::from_input(input);
+/// Every beam state (row, col, dir) in a layout, numbered densely, with the states
+/// a beam in each one moves on to.
+///
+/// The states are condensed into strongly connected components, so a splitter loop
+/// becomes a single node. Tarjan's algorithm numbers the components so that every
+/// component comes after all the components reachable from it, which lets the
+/// energized tiles of each one be built from those of its successors.
+struct BeamGraph {
+    num_cols: usize,
+    /// The component of each state.
+    comp: Vec<usize>,
+    /// The tiles covered by each component, as `row * num_cols + col`.
+    comp_tiles: Vec<Vec<usize>>,
+    /// The components each component leads into, without duplicates.
+    comp_next: Vec<Vec<usize>>,
+}
+
+impl BeamGraph {
+    fn new(layout: &Layout) -> BeamGraph {
+        let num_rows = layout.spaces.len();
+        let num_cols = layout.spaces[0].len();
+        let mut next = Vec::with_capacity(num_rows * num_cols * 4);
+        for row in 0..num_rows {
+            for col in 0..num_cols {
+                for dir in DIRS {
+                    next.push(
+                        step(&(row, col, dir), layout)
+                            .iter()
+                            .map(|pos| state_id(pos, num_cols))
+                            .collect::<Vec<usize>>(),
+                    );
+                }
+            }
+        }
+
+        let (comp, num_comps) = strongly_connected(&next);
+        let mut comp_tiles = vec![Vec::new(); num_comps];
+        let mut comp_next = vec![Vec::new(); num_comps];
+        for (state, targets) in next.iter().enumerate() {
+            let c = comp[state];
+            let tile = state / 4;
+            if !comp_tiles[c].contains(&tile) {
+                comp_tiles[c].push(tile);
+            }
+            for target in targets {
+                let t = comp[*target];
+                if t != c && !comp_next[c].contains(&t) {
+                    comp_next[c].push(t);
+                }
+            }
+        }
+
+        BeamGraph {
+            num_cols,
+            comp,
+            comp_tiles,
+            comp_next,
+        }
+    }
+
+    /// The tiles energized by a beam entering at each of `entries`, as bitsets over
+    /// `row * num_cols + col`.
+    fn energized_bits(&self, entries: &[Pos]) -> Vec<Vec<u64>> {
+        let num_comps = self.comp_tiles.len();
+        let entry_comps = entries
+            .iter()
+            .map(|pos| self.comp[state_id(pos, self.num_cols)])
+            .collect::<Vec<usize>>();
+
+        // Only the components some entry can reach are needed, and a component's
+        // tiles can be dropped once every needed component leading into it is done.
+        let mut needed = vec![false; num_comps];
+        let mut keep = vec![false; num_comps];
+        let mut todo = entry_comps.clone();
+        for c in entry_comps.iter() {
+            keep[*c] = true;
+        }
+        while let Some(c) = todo.pop() {
+            if needed[c] {
+                continue;
+            }
+            needed[c] = true;
+            todo.extend(self.comp_next[c].iter().filter(|t| !needed[**t]));
+        }
+        let mut waiting = vec![0; num_comps];
+        for c in (0..num_comps).filter(|c| needed[*c]) {
+            for t in self.comp_next[c].iter() {
+                waiting[*t] += 1;
+            }
+        }
+
+        let words = (self.comp.len() / 4).div_ceil(64);
+        let mut tiles: Vec<Option<Vec<u64>>> = vec![None; num_comps];
+        for c in (0..num_comps).filter(|c| needed[*c]) {
+            let mut bits = vec![0u64; words];
+            for tile in self.comp_tiles[c].iter() {
+                bits[tile / 64] |= 1 << (tile % 64);
+            }
+            for t in self.comp_next[c].iter() {
+                for (word, other) in bits.iter_mut().zip(tiles[*t].as_ref().unwrap()) {
+                    *word |= other;
+                }
+                waiting[*t] -= 1;
+                if waiting[*t] == 0 && !keep[*t] {
+                    tiles[*t] = None;
+                }
+            }
+            tiles[c] = Some(bits);
+        }
+
+        entry_comps
+            .iter()
+            .map(|c| tiles[*c].clone().unwrap())
+            .collect()
+    }
+
+    /// The (row, col) of every tile energized by a beam entering at each of
+    /// `entries`, in reading order.
+    fn energized_tile_sets(&self, entries: &[Pos]) -> Vec<Vec<(usize, usize)>> {
+        self.energized_bits(entries)
+            .iter()
+            .map(|bits| {
+                (0..self.comp.len() / 4)
+                    .filter(|tile| bits[tile / 64] & (1 << (tile % 64)) != 0)
+                    .map(|tile| (tile / self.num_cols, tile % self.num_cols))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn energized_tiles(&self, entry: &Pos) -> Vec<(usize, usize)> {
+        self.energized_tile_sets(&[*entry]).swap_remove(0)
+    }
+
+    /// The number of tiles energized by a beam entering at each of `entries`.
+    fn energized(&self, entries: &[Pos]) -> Vec<usize> {
+        self.energized_bits(entries)
+            .iter()
+            .map(|bits| bits.iter().map(|word| word.count_ones() as usize).sum())
+            .collect()
+    }
+}
+
+fn state_id(pos: &Pos, num_cols: usize) -> usize {
+    (pos.0 * num_cols + pos.1) * 4 + pos.2.index()
+}
+
+/// Tarjan's algorithm, without recursion. Returns the component of every node and
+/// the number of components, numbered in the order they are completed.
+fn strongly_connected(next: &[Vec<usize>]) -> (Vec<usize>, usize) {
+    let n = next.len();
+    let mut index = vec![usize::MAX; n];
+    let mut low = vec![0; n];
+    let mut on_stack = vec![false; n];
+    let mut stack = Vec::new();
+    let mut comp = vec![usize::MAX; n];
+    let mut num_comps = 0;
+    let mut counter = 0;
+
+    for root in 0..n {
+        if index[root] != usize::MAX {
+            continue;
+        }
+        let mut calls = vec![(root, 0)];
+        index[root] = counter;
+        low[root] = counter;
+        counter += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some(&(v, i)) = calls.last() {
+            if i < next[v].len() {
+                calls.last_mut().unwrap().1 += 1;
+                let w = next[v][i];
+                if index[w] == usize::MAX {
+                    index[w] = counter;
+                    low[w] = counter;
+                    counter += 1;
+                    stack.push(w);
+                    on_stack[w] = true;
+                    calls.push((w, 0));
+                } else if on_stack[w] {
+                    low[v] = low[v].min(index[w]);
+                }
+                continue;
+            }
+            calls.pop();
+            if let Some(&(u, _)) = calls.last() {
+                low[u] = low[u].min(low[v]);
+            }
+            if low[v] == index[v] {
+                loop {
+                    let w = stack.pop().unwrap();
+                    on_stack[w] = false;
+                    comp[w] = num_comps;
+                    if w == v {
+                        break;
+                    }
+                }
+                num_comps += 1;
+            }
+        }
+    }
+
+    (comp, num_comps)
+}
+
+/// Every beam entering the layout from an edge.
+fn entry_points(layout: &Layout) -> Vec<Pos> {
+    let num_rows = layout.spaces.len();
+    let num_cols = layout.spaces[0].len();
     let mut start = Vec::new();
 
-    for row in 0..layout.spaces.len() {
+    for row in 0..num_rows {
         start.push((row, 0, Dir::Right));
-        start.push((row, layout.spaces[0].len() - 1, Dir::Left));
+        start.push((row, num_cols - 1, Dir::Left));
     }
 
-    for col in 0..layout.spaces[0].len() {
+    for col in 0..num_cols {
         start.push((0, col, Dir::Down));
-        start.push((layout.spaces.len() - 1, col, Dir::Up));
+        start.push((num_rows - 1, col, Dir::Up));
+    }
+
+    start
+}
+
+fn part_2(input: &str) -> usize {
+    let layout = Layout::from_input(input);
+    let start = entry_points(&layout);
+    BeamGraph::new(&layout)
+        .energized(&start)
+        .into_iter()
+        .max()
+        .unwrap()
+}
+
+/// How a beam spreads through a layout, one step per tick.
+struct Propagation {
+    /// The first tick at which each tile is energized, if it ever is.
+    first_energized: Vec<Vec<Option<usize>>>,
+    /// The number of beams moving into a state not seen before, on each tick.
+    /// Beams that split are counted separately, and beams that merge or fall
+    /// into a loop they have already traced stop being counted.
+    active: Vec<usize>,
+}
+
+impl Propagation {
+    /// The tick from which nothing changes any more.
+    fn steady_after(&self) -> usize {
+        self.active.len()
+    }
+
+    fn energized(&self) -> usize {
+        self.first_energized.iter().flatten().flatten().count()
+    }
+}
+
+/// Runs a beam entering at `start` breadth first, tick 0 being the entry itself.
+fn propagate(layout: &Layout, start: &Pos) -> Propagation {
+    let mut first_energized = vec![vec![None; layout.spaces[0].len()]; layout.spaces.len()];
+    let mut active = Vec::new();
+    let mut visited = HashSet::from([*start]);
+    let mut frontier = vec![*start];
+
+    while !frontier.is_empty() {
+        let tick = active.len();
+        active.push(frontier.len());
+        let mut next = Vec::new();
+        for pos in frontier.iter() {
+            first_energized[pos.0][pos.1].get_or_insert(tick);
+            for p in step(pos, layout) {
+                if visited.insert(p) {
+                    next.push(p);
+                }
+            }
+        }
+        frontier = next;
+    }
+
+    Propagation {
+        first_energized,
+        active,
+    }
+}
+
+/// How to draw a layout lit by a beam.
+#[derive(Clone, Copy)]
+enum View {
+    /// `#` for energized tiles and `.` for the rest.
+    Energized,
+    /// The layout itself, with the beams drawn on its empty tiles: an arrow where
+    /// a single beam passes, otherwise the number of beams crossing there.
+    Arrows,
+}
+
+fn render(layout: &Layout, start: &Pos, view: View) -> String {
+    let num_cols = layout.spaces[0].len();
+    let mut dirs = vec![Vec::new(); layout.spaces.len() * num_cols];
+    for (row, col, dir) in beam_states(layout, start) {
+        dirs[row * num_cols + col].push(dir);
     }
 
-    start.iter().map(|s| num_tiles(&layout, s)).max().unwrap()
+    layout
+        .spaces
+        .iter()
+        .enumerate()
+        .map(|(row, line)| {
+            line.iter()
+                .enumerate()
+                .map(|(col, space)| {
+                    let beams = &dirs[row * num_cols + col];
+                    match (view, space.is_empty(), beams.len()) {
+                        (View::Energized, _, 0) => '.',
+                        (View::Energized, _, _) => '#',
+                        (View::Arrows, true, 1) => beams[0].to_char(),
+                        (View::Arrows, true, n) if n > 1 => char::from_digit(n as u32, 10).unwrap(),
+                        (View::Arrows, _, _) => space.to_char(),
+                    }
+                })
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The entry point energizing the most tiles, and how many it energizes. Ties go
+/// to the earliest entry in `entry_points` order.
+fn best_entry(layout: &Layout) -> (Pos, usize) {
+    let start = entry_points(layout);
+    let energized = BeamGraph::new(layout).energized(&start);
+    let mut best = 0;
+    for (idx, tiles) in energized.iter().enumerate() {
+        if *tiles > energized[best] {
+            best = idx;
+        }
+    }
+    (start[best], energized[best])
+}
+
+/// The best entry point, with the layout rendered as lit from it.
+fn render_best(layout: &Layout, view: View) -> (Pos, usize, String) {
+    let (start, tiles) = best_entry(layout);
+    (start, tiles, render(layout, &start, view))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::aoc_2023::data::day_16::*;
+    use crate::aoc_2023::Rng;
 
     #[test]
     fn part_1_sample() {
@@ -192,4 +645,257 @@ mod tests {
         let result = part_2(&input);
         assert_eq!(result, 8225);
     }
+
+    fn random_layout(rng: &mut Rng, num_rows: usize, num_cols: usize) -> String {
+        (0..num_rows)
+            .map(|_| {
+                (0..num_cols)
+                    .map(|_| match rng.below(10) {
+                        0 => '/',
+                        1 => '\\',
+                        2 => '-',
+                        3 => '|',
+                        _ => '.',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+
+    fn check_against_flood(layout: &Layout) {
+        let start = entry_points(layout);
+        let expected = start
+            .iter()
+            .map(|s| num_tiles(layout, s))
+            .collect::<Vec<usize>>();
+        let graph = BeamGraph::new(layout);
+        assert_eq!(graph.energized(&start), expected);
+        for (entry, tiles) in start.iter().zip(graph.energized_tile_sets(&start)) {
+            let mut flood = beam_states(layout, entry)
+                .iter()
+                .map(|pos| (pos.0, pos.1))
+                .collect::<HashSet<(usize, usize)>>()
+                .into_iter()
+                .collect::<Vec<(usize, usize)>>();
+            flood.sort();
+            assert_eq!(tiles, flood);
+        }
+    }
+
+    #[test]
+    fn beam_graph_sample() {
+        let input = read_input(InputFile::SAMPLE);
+        let layout = Layout::from_input(&input);
+        check_against_flood(&layout);
+        assert_eq!(
+            BeamGraph::new(&layout).energized(&[(0, 0, Dir::Right), (0, 3, Dir::Down)]),
+            vec![46, 51]
+        );
+        let tiles = BeamGraph::new(&layout).energized_tiles(&(0, 0, Dir::Right));
+        assert_eq!(tiles.len(), 46);
+        assert_eq!(
+            &tiles[..7],
+            &[(0, 0), (0, 1), (0, 2), (0, 3), (0, 4), (0, 5), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn beam_graph_matches_flood() {
+        let mut rng = Rng::new(0x2023_1216);
+        for (num_rows, num_cols) in [(1, 1), (1, 7), (6, 1), (5, 5), (9, 13), (20, 20)] {
+            for _ in 0..10 {
+                let input = random_layout(&mut rng, num_rows, num_cols);
+                check_against_flood(&Layout::from_input(&input));
+            }
+        }
+    }
+
+    #[test]
+    fn render_sample() {
+        let input = read_input(InputFile::SAMPLE);
+        let layout = Layout::from_input(&input);
+        let start = (0, 0, Dir::Right);
+        assert_eq!(
+            render(&layout, &start, View::Energized),
+            "######....\n\
+             .#...#....\n\
+             .#...#####\n\
+             .#...##...\n\
+             .#...##...\n\
+             .#...##...\n\
+             .#..####..\n\
+             ########..\n\
+             .#######..\n\
+             .#...#.#.."
+        );
+        assert_eq!(
+            render(&layout, &start, View::Arrows),
+            ">|<<<\\....\n\
+             |v-.\\^....\n\
+             .v...|->>>\n\
+             .v...v^.|.\n\
+             .v...v^...\n\
+             .v...v^..\\\n\
+             .v../2\\\\..\n\
+             <->-/vv|..\n\
+             .|<<<2-|.\\\n\
+             .v//.|.v.."
+        );
+    }
+
+    #[test]
+    fn render_best_sample() {
+        let input = read_input(InputFile::SAMPLE);
+        let layout = Layout::from_input(&input);
+        let (start, tiles, picture) = render_best(&layout, View::Energized);
+        assert!(start == (0, 3, Dir::Down));
+        assert_eq!(tiles, 51);
+        assert_eq!(
+            picture,
+            ".#####....\n\
+             .#.#.#....\n\
+             .#.#.#####\n\
+             .#.#.##...\n\
+             .#.#.##...\n\
+             .#.#.##...\n\
+             .#.#####..\n\
+             ########..\n\
+             .#######..\n\
+             .#...#.#.."
+        );
+        assert_eq!(picture.matches('#').count(), 51);
+    }
+
+    #[test]
+    fn parse_unknown_element() {
+        assert_eq!(
+            Layout::parse("..\n.X", &Elements::standard()).err(),
+            Some(UnknownElement {
+                row: 1,
+                col: 1,
+                symbol: 'X'
+            })
+        );
+        assert!(Layout::parse("..\n.X", &Elements::extended()).is_ok());
+        assert!(Layout::parse("..\n.#", &Elements::extended()).is_err());
+    }
+
+    #[test]
+    fn extended_elements() {
+        let elements = Elements::extended();
+        let layout = Layout::parse(".X..\n....\n.*.W\n....", &elements).unwrap();
+        assert_eq!(
+            render(&layout, &(0, 0, Dir::Right), View::Energized),
+            "##..\n....\n....\n...."
+        );
+        assert_eq!(
+            render(&layout, &(0, 1, Dir::Down), View::Energized),
+            ".#..\n....\n....\n...."
+        );
+        assert_eq!(
+            render(&layout, &(2, 0, Dir::Right), View::Arrows),
+            ".X..\n.^..\n>*>W\n.v.."
+        );
+        assert_eq!(
+            render(&layout, &(2, 3, Dir::Down), View::Energized),
+            "....\n....\n...#\n...."
+        );
+
+        let mut elements = Elements::standard();
+        elements
+            .define('x', |dir| match dir {
+                Dir::Up | Dir::Down => vec![dir],
+                Dir::Left | Dir::Right => vec![],
+            })
+            .unwrap();
+        let layout = Layout::parse("x.\n..", &elements).unwrap();
+        assert_eq!(num_tiles(&layout, &(0, 0, Dir::Right)), 1);
+        assert_eq!(num_tiles(&layout, &(0, 0, Dir::Down)), 2);
+
+        // A pass-through element gets beams drawn over it like empty space does.
+        elements.define('o', |dir| vec![dir]).unwrap();
+        let layout = Layout::parse("o.\n|.", &elements).unwrap();
+        assert!(layout.spaces[0][0].is_empty());
+        assert!(!layout.spaces[1][0].is_empty());
+        assert_eq!(render(&layout, &(0, 0, Dir::Right), View::Arrows), ">>\n|.");
+    }
+
+    #[test]
+    fn reserved_symbols() {
+        let mut elements = Elements::extended();
+        assert!(elements
+            .spaces
+            .keys()
+            .all(|&symbol| !RESERVED_SYMBOLS.contains(symbol)));
+        assert_eq!(
+            elements.define('>', |_| vec![]),
+            Err(ReservedSymbol { symbol: '>' })
+        );
+        assert_eq!(
+            elements.define('7', |dir| vec![dir]),
+            Err(ReservedSymbol { symbol: '7' })
+        );
+        assert!(elements.get('>').is_none());
+        assert_eq!(elements.define('@', |_| vec![]), Ok(()));
+        assert!(elements.get('@').is_some());
+    }
+
+    #[test]
+    fn extended_beam_graph_matches_flood() {
+        let elements = Elements::extended();
+        let mut rng = Rng::new(0x2023_1216_0047);
+        let symbols = ['/', '\\', '-', '|', 'X', 'N', 'S', 'W', 'E', '*'];
+        for _ in 0..30 {
+            let input = (0..9)
+                .map(|_| {
+                    (0..11)
+                        .map(|_| match rng.below(20) {
+                            n if n < symbols.len() => symbols[n],
+                            _ => '.',
+                        })
+                        .collect::<String>()
+                })
+                .collect::<Vec<String>>()
+                .join("\n");
+            check_against_flood(&Layout::parse(&input, &elements).unwrap());
+        }
+    }
+
+    #[test]
+    fn propagate_small() {
+        let layout = Layout::from_input("..|..\n.....\n..-..");
+        let propagation = propagate(&layout, &(0, 0, Dir::Right));
+        assert_eq!(
+            propagation.first_energized,
+            vec![
+                vec![Some(0), Some(1), Some(2), None, None],
+                vec![None, None, Some(3), None, None],
+                vec![Some(6), Some(5), Some(4), Some(5), Some(6)],
+            ]
+        );
+        assert_eq!(propagation.active, vec![1, 1, 1, 1, 1, 2, 2]);
+        assert_eq!(propagation.steady_after(), 7);
+        assert_eq!(propagation.energized(), 9);
+    }
+
+    #[test]
+    fn propagate_sample() {
+        let input = read_input(InputFile::SAMPLE);
+        let layout = Layout::from_input(&input);
+        for start in entry_points(&layout) {
+            let propagation = propagate(&layout, &start);
+            assert_eq!(propagation.energized(), num_tiles(&layout, &start));
+            assert_eq!(
+                propagation.active.iter().sum::<usize>(),
+                beam_states(&layout, &start).len()
+            );
+            assert_eq!(propagation.first_energized[start.0][start.1], Some(0));
+            let last = propagation.first_energized.iter().flatten().flatten().max();
+            assert!(*last.unwrap() < propagation.steady_after());
+        }
+        let propagation = propagate(&layout, &(0, 0, Dir::Right));
+        assert_eq!(propagation.energized(), 46);
+        assert_eq!(propagation.first_energized[9][7], Some(18));
+    }
 }